@@ -100,12 +100,15 @@ fn main() {
         TSDL_BUILD_DIR     : str  = json(tsdl, "build-dir"),
         TSDL_CACHE_FILE    : str  = json(tsdl, "cache-file"),
         TSDL_CONFIG_FILE   : str  = json(tsdl, "config-file"),
+        TSDL_FAILED_FILE   : str  = json(tsdl, "failed-file"),
         TSDL_FORCE         : bool = json(tsdl, "force"),
         TSDL_FRESH         : bool = json(tsdl, "fresh"),
         TSDL_FROM          : str  = json(tsdl, "from"),
         TSDL_LOCK_FILE     : str  = json(tsdl, "lock-file"),
+        TSDL_MANIFEST_FILE : str  = json(tsdl, "manifest-file"),
         TSDL_OUT_DIR       : str  = json(tsdl, "out-dir"),
         TSDL_PREFIX        : str  = json(tsdl, "prefix"),
+        TSDL_QUERIES_DIR   : str  = json(tsdl, "queries-dir"),
         TSDL_REF           : str  = json(tsdl, "ref"),
         TSDL_SHOW_CONFIG   : bool = json(tsdl, "show-config"),
     );
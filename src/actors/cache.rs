@@ -160,15 +160,7 @@ impl CacheActor {
                             language: &language,
                         },
                     }
-                    .send(
-                        self.force
-                            || self
-                                .db
-                                .parsers
-                                .iter()
-                                .find(|(key, _)| key.starts_with(&format!("{language}/")))
-                                .is_none_or(|(_, entry)| entry.spec != spec),
-                    );
+                    .send(self.force || self.db.needs_clone(&language, &spec));
                 }
 
                 CacheMessage::Get { name, tx } => {
@@ -1,7 +1,14 @@
 mod cache;
 mod display;
 
-use std::{path::PathBuf, sync::Arc};
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    fmt,
+    path::{Path, PathBuf},
+    pin::pin,
+    sync::Arc,
+};
 
 pub use cache::{CacheActor, CacheAddr};
 pub use display::{DisplayActor, DisplayAddr, DisplayMessage, ProgressAddr};
@@ -10,7 +17,13 @@ use tokio::sync::{mpsc, oneshot};
 
 use crate::{
     args::TreeSitter,
+    cancel,
     error::TsdlError,
+    failed::Failed,
+    install::Transaction,
+    layout::Claims,
+    limits::Limits,
+    manifest,
     parser::{GrammarBuild, LanguageBuild},
     tree_sitter, TsdlResult,
 };
@@ -67,74 +80,270 @@ pub async fn run(
     build_dir: &PathBuf,
     cache: CacheAddr,
     display: DisplayAddr,
-    jobs: usize,
+    limits: &Limits,
     languages: Vec<LanguageBuild>,
+    mut transaction: Transaction,
     tree_sitter: &TreeSitter,
 ) -> TsdlResult<()> {
     let ts_cli = Arc::new(tree_sitter::prepare(build_dir, display.clone(), tree_sitter).await?);
 
-    let mut errors : Vec<TsdlError> =
-      // 1. Source: Create a stream from the input list
-      stream::iter(languages)
-          // 2. Stage: Discovery
-          // Transform Language -> Future<Result<Vec<Grammar>>>
-          .map(|language| {
-              let (cache, display, ts_cli) = (cache.clone(), display.clone(), ts_cli.clone());
-              async move {
-                  // We refactor `discover` to return the list instead of sending messages
-                  discover_grammars(cache, display, language, ts_cli).await
-              }
-          })
-          // Run up to `concurrency` discovery tasks at once
-          .buffer_unordered(jobs)
-          // 3. Flattening & Error Propagation
-          // Turn the stream of "Lists of Grammars" into a flat stream of "Individual Grammars"
-          // If discovery failed, pass the error through as an Item
-          .flat_map(|discovery_result| match discovery_result {
-              Ok(grammars) => stream::iter(grammars).map(Ok).left_stream(),
-              Err(e) => stream::once(async { Err(e) }).right_stream(),
-          })
-          // 4. Stage: Build
-          // Transform Result<Grammar> -> Future<Result<BuildOutcome>>
-          .map(|item| async move {
-              match item {
-                  Ok(grammar_build) => {
-                      // Execute the build logic
-                      // logic formerly in GrammarActor
-                      grammar_build.build().await
-                  }
-                  Err(e) => Err(e), // Pass upstream discovery errors down
-              }
-          })
-          // Run up to `concurrency` build tasks at once
-          .buffer_unordered(jobs)
-          // 5. Sink: Accumulator
-          // Fold the stream into the final error vector.
-          // This implicitly waits for ALL tasks to finish.
-          .fold(Vec::new(), |mut errors, result| {
-              let cache = cache.clone();
-              async move {
-                  match result {
-                      Ok(Some(update)) => cache.update(update).await, // Side-effect: Cache Update
-                      Ok(None) => {}                                  // Cache hit
-                      Err(e) => errors.push(e),                       // Accumulate Error
-                  }
-                  errors
-              }
-          })
-          .await;
+    let summary = RefCell::new(Summary::new(languages.iter().map(|l| l.name.clone())));
+    let claims = Claims::default();
+    let cache = &cache;
+
+    let mut results = pin!(
+        // 1. Source: Create a stream from the input list
+        stream::iter(languages)
+            // 2. Stage: Discovery
+            // Transform Language -> Future<(Name, Result<Vec<Grammar>>)>
+            .map(|language| {
+                let (cache, display, ts_cli) = (cache.clone(), display.clone(), ts_cli.clone());
+                async move {
+                    let name = language.name.clone();
+                    (
+                        name,
+                        discover_grammars(cache, display, language, ts_cli).await,
+                    )
+                }
+            })
+            // Run up to `fetch_jobs` discovery tasks at once
+            .buffer_unordered(limits.fetch_jobs)
+            // 3. Claims
+            // Each language claims its files as soon as it's discovered, so its
+            // grammars start building while the others are still fetched; one
+            // whose grammars would install over another's fails before any of
+            // them is built.
+            .map(|(language, discovery_result)| {
+                let claimed = discovery_result.and_then(|grammars| claim(&claims, grammars));
+                (language, claimed)
+            })
+            // 4. Flattening & Error Propagation
+            // Turn the stream of "Lists of Grammars" into a flat stream of "Individual Grammars"
+            // If discovery failed, pass the error through as an Item
+            .flat_map(|(language, discovery_result)| match discovery_result {
+                Ok(grammars) => {
+                    summary.borrow_mut().discovered(&language, grammars.len());
+                    stream::iter(grammars)
+                        .map(move |grammar| {
+                            let key = format!("{}/{}", language, grammar.name);
+                            (language.clone(), key, Ok(grammar))
+                        })
+                        .left_stream()
+                }
+                Err(e) => {
+                    let key = language.to_string();
+                    stream::once(async { (language, key, Err(e)) }).right_stream()
+                }
+            })
+            // 5. Stage: Build
+            // Transform (Name, Key, Result<Grammar>) -> Future<(Name, Key, Result<(Outcome, manifest::Grammar)>)>
+            .map(|(language, key, item)| async move {
+                let result = match item {
+                    Ok(grammar_build) => build(&grammar_build, cache, limits).await,
+                    Err(e) => Err(e), // Pass upstream discovery errors down
+                };
+                (language, key, result)
+            })
+            // Run up to `build_jobs` build tasks at once
+            .buffer_unordered(limits.build_jobs)
+            // Stop on SIGINT/SIGTERM, or once too many failed; what's finished
+            // so far still gets cached.
+            .take_until(cancel::cancelled())
+    );
+
+    // 6. Sink: Accumulator
+    let mut errors: Vec<TsdlError> = Vec::new();
+    let mut failed_fast = false;
+    let mut failures = Vec::new();
+    while let Some((language, key, result)) = results.next().await {
+        let outcome = match result {
+            Ok((outcome, grammar)) => {
+                transaction.record(grammar);
+                outcome
+            }
+            Err(e) => {
+                errors.push(e);
+                failures.push(key);
+                Outcome::Failed
+            }
+        };
+        let done = summary.borrow_mut().finished(&language, outcome);
+        if done == Some(Outcome::Built) || done == Some(Outcome::Cached) {
+            if let Err(e) = transaction.language_done(&language) {
+                errors.push(e);
+            }
+        }
+
+        if limits.max_failures.is_some_and(|max| errors.len() >= max) && !failed_fast {
+            failed_fast = true;
+            cancel::cancel();
+        }
+    }
+
+    if let Err(e) = transaction.finish(errors.is_empty() && !cancel::is_cancelled()) {
+        errors.push(e);
+    }
 
     if let Err(e) = cache.save().await {
         errors.push(e);
     }
 
-    if errors.is_empty() {
+    let summary = summary.take();
+    if let Err(e) = save_failures(build_dir, &summary, failures) {
+        errors.push(e);
+    }
+    if !summary.is_empty() {
+        display.println(summary.to_string()).await;
+    }
+
+    // The summary and failures above already cover what finished before the
+    // cancel; the errors of what failed still get reported.
+    match (cancel::is_cancelled() && !failed_fast, errors.is_empty()) {
+        (true, true) => Err(TsdlError::message("Build cancelled")),
+        (true, false) => Err(TsdlError::context(
+            "Build cancelled",
+            TsdlError::Build(errors),
+        )),
+        (false, true) => Ok(()),
+        (false, false) => Err(TsdlError::Build(errors)),
+    }
+}
+
+/// How a language fared; a language takes the worst outcome of its grammars.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Outcome {
+    Cached,
+    Built,
+    Skipped,
+    Failed,
+}
+
+/// What happened to each language, for the end of a build.
+#[derive(Debug, Default)]
+pub struct Summary {
+    languages: BTreeMap<Arc<str>, (Option<Outcome>, Option<usize>)>,
+}
+
+impl Summary {
+    pub fn new(languages: impl IntoIterator<Item = Arc<str>>) -> Self {
+        Self {
+            languages: languages
+                .into_iter()
+                .map(|name| (name, (None, None)))
+                .collect(),
+        }
+    }
+
+    /// `language` has `count` grammars to build.
+    pub fn discovered(&mut self, language: &Arc<str>, count: usize) {
+        self.languages.entry(language.clone()).or_default().1 = Some(count);
+    }
+
+    /// One of `language`'s grammars, or its discovery, is done. Returns
+    /// the language's outcome once all of its grammars are.
+    pub fn finished(&mut self, language: &Arc<str>, outcome: Outcome) -> Option<Outcome> {
+        let (current, pending) = self.languages.entry(language.clone()).or_default();
+        *current = (*current).max(Some(outcome));
+        match pending {
+            Some(pending) => {
+                *pending = pending.saturating_sub(1);
+                (*pending == 0).then_some(*current).flatten()
+            }
+            None => None,
+        }
+    }
+
+    /// Languages with grammars that never finished were skipped.
+    #[must_use]
+    pub fn outcome(&self, language: &str) -> Option<Outcome> {
+        self.languages.get(language).map(|(outcome, pending)| {
+            let skipped = pending.is_none_or(|pending| pending > 0);
+            match outcome {
+                Some(Outcome::Failed) => Outcome::Failed,
+                _ if skipped => Outcome::Skipped,
+                Some(outcome) => *outcome,
+                None => Outcome::Cached,
+            }
+        })
+    }
+
+    fn languages(&self, outcome: Outcome) -> Vec<&str> {
+        self.languages
+            .keys()
+            .filter(|name| self.outcome(name) == Some(outcome))
+            .map(AsRef::as_ref)
+            .collect()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.languages.is_empty()
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut first = true;
+        for (label, outcome) in [
+            ("Built", Outcome::Built),
+            ("Cached", Outcome::Cached),
+            ("Skipped", Outcome::Skipped),
+            ("Failed", Outcome::Failed),
+        ] {
+            let languages = self.languages(outcome);
+            if languages.is_empty() {
+                continue;
+            }
+            if !first {
+                writeln!(f)?;
+            }
+            first = false;
+            write!(f, "{label}: {}", languages.join(", "))?;
+        }
         Ok(())
-    } else {
-        Err(TsdlError::Build(errors))
     }
 }
 
+/// Build `grammar` and describe it for the manifest. A grammar that was
+/// built is cached even when describing it fails, which fails its install.
+async fn build(
+    grammar: &GrammarBuild,
+    cache: &CacheAddr,
+    limits: &Limits,
+) -> TsdlResult<(Outcome, manifest::Grammar)> {
+    let outcome = match grammar.build(limits.memory.as_ref()).await? {
+        Some(update) => {
+            cache.update(update).await;
+            Outcome::Built
+        }
+        None => Outcome::Cached,
+    };
+    Ok((outcome, grammar.manifest().await?))
+}
+
+/// Claim the files `grammars` install.
+fn claim(claims: &Claims, grammars: Vec<GrammarBuild>) -> TsdlResult<Vec<GrammarBuild>> {
+    for grammar in &grammars {
+        let key = format!("{}/{}", grammar.language, grammar.name);
+        claims.claim(&key, &grammar.installed_files())?;
+    }
+    Ok(grammars)
+}
+
+/// Remember what failed for `tsdl build --failed`.
+fn save_failures(build_dir: &Path, summary: &Summary, failures: Vec<String>) -> TsdlResult<()> {
+    let mut failed = Failed::load(build_dir)?;
+    failed.update(
+        |language| {
+            summary
+                .outcome(language)
+                .is_some_and(|outcome| outcome != Outcome::Skipped)
+        },
+        failures,
+    );
+    failed.save(build_dir)
+}
+
 // --- Helper Refactors (Moving logic out of Actor impls) ---
 
 async fn discover_grammars(
@@ -158,10 +367,12 @@ async fn discover_grammars(
 
     progress.step("scanning");
     let grammars = language.discover_grammars().await?;
+    let version = language.version().await?;
+    let license = language.license().await?;
 
     // Map the raw discovery data into the Build struct immediately
     let mut builds = Vec::new();
-    for (name, dir, hash) in grammars {
+    for (name, dir, sources) in grammars {
         let key = format!("{}/{}", language.name, name);
         let entry = cache.get(key).await;
         let name_arc: std::sync::Arc<str> = name.into();
@@ -179,15 +390,49 @@ async fn discover_grammars(
             context: language.context.clone(),
             dir: dir.into(),
             entry,
-            hash: hash.into(),
+            hash: sources.hash().into(),
+            files: sources.into(),
             language: language.name.clone(),
+            license: license.clone(),
             name: name_arc,
             output: language.output.clone(),
             progress,
             spec: language.spec.clone(),
             ts_cli: ts_cli.clone(),
+            version: version.clone(),
         });
     }
 
     Ok(builds)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary() {
+        let [json, rust, ruby, java, go]: [Arc<str>; 5] =
+            ["json", "rust", "ruby", "java", "go"].map(Into::into);
+        let mut summary = Summary::new([&json, &rust, &ruby, &java, &go].map(Clone::clone));
+
+        summary.discovered(&json, 1);
+        summary.finished(&json, Outcome::Cached);
+        summary.discovered(&rust, 2);
+        summary.finished(&rust, Outcome::Cached);
+        summary.finished(&rust, Outcome::Built);
+        summary.discovered(&ruby, 2);
+        summary.finished(&ruby, Outcome::Built);
+        summary.finished(&java, Outcome::Failed);
+
+        assert_eq!(summary.outcome("json"), Some(Outcome::Cached));
+        assert_eq!(summary.outcome("rust"), Some(Outcome::Built));
+        assert_eq!(summary.outcome("ruby"), Some(Outcome::Skipped));
+        assert_eq!(summary.outcome("java"), Some(Outcome::Failed));
+        assert_eq!(summary.outcome("go"), Some(Outcome::Skipped));
+        assert_eq!(
+            summary.to_string(),
+            "Built: rust\nCached: json\nSkipped: go, ruby\nFailed: java"
+        );
+    }
+}
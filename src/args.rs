@@ -1,4 +1,8 @@
-use std::{collections::BTreeMap, fmt, path::PathBuf};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    path::{Path, PathBuf},
+};
 
 use clap::{
     builder::styling::{AnsiColor, Color, Style},
@@ -10,7 +14,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::consts::{
     TREE_SITTER_PLATFORM, TREE_SITTER_REPO, TREE_SITTER_VERSION, TSDL_BUILD_DIR, TSDL_CONFIG_FILE,
-    TSDL_FORCE, TSDL_FRESH, TSDL_OUT_DIR, TSDL_PREFIX, TSDL_SHOW_CONFIG,
+    TSDL_FORCE, TSDL_FRESH, TSDL_OUT_DIR, TSDL_PREFIX, TSDL_QUERIES_DIR, TSDL_SHOW_CONFIG,
 };
 
 const TSDL_VERSION: &str = include_str!(concat!(env!("OUT_DIR"), "/tsdl.version"));
@@ -74,6 +78,13 @@ pub enum Command {
     #[command(visible_alias = "b")]
     Build(BuildCommand),
 
+    /// Inspect the build cache.
+    #[serde(skip_serializing, skip_deserializing)]
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+
     /// Configuration helpers.
     #[serde(skip_serializing, skip_deserializing)]
     #[command(visible_alias = "c")]
@@ -82,10 +93,48 @@ pub enum Command {
         command: ConfigCommand,
     },
 
+    /// Bundle the parsers in `out-dir`, with their manifest, into an archive.
+    #[serde(skip_serializing, skip_deserializing)]
+    Pack {
+        #[arg(long, value_enum, default_value_t)]
+        format: ArchiveFormat,
+
+        /// Where to write the archive; `<out-dir>.<format>` by default.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Include the grammars' installed queries.
+        #[arg(long)]
+        queries: bool,
+    },
+
+    /// Restore the parsers in `out-dir` that the last install replaced.
+    #[serde(skip_serializing, skip_deserializing)]
+    Rollback,
+
     /// Update tsdl to its latest version.
     #[serde(skip_serializing, skip_deserializing)]
     #[command(visible_alias = "u")]
     Selfupdate,
+
+    /// Install the parsers of an archive made by `tsdl pack` into `out-dir`.
+    #[serde(skip_serializing, skip_deserializing)]
+    Unpack {
+        /// A `.tar.zst`, `.tar.gz` or `.zip` archive.
+        archive: PathBuf,
+
+        /// Overwrite files in `out-dir` that differ from the archive's.
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Check that the parsers in `out-dir` are the ones that were built.
+    #[serde(skip_serializing, skip_deserializing)]
+    Verify {
+        /// Reinstall what doesn't match from `build-dir`, where it still can be.
+        #[arg(long)]
+        repair: bool,
+    },
 }
 
 impl Command {
@@ -108,36 +157,344 @@ impl Command {
     }
 }
 
-#[derive(clap::ValueEnum, Clone, Copy, Debug, Deserialize, Diff, PartialEq, Eq, Serialize)]
+#[derive(
+    clap::ValueEnum,
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    Deserialize,
+    Diff,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Serialize,
+)]
 #[diff(attr(
     #[derive(Debug, PartialEq)]
 ))]
 #[serde(rename_all = "kebab-case")]
-#[derive(Default)]
 pub enum Target {
+    /// Shared library for the host.
     #[default]
     Native,
+    /// Static library for the host: `lib<prefix><name>.a`.
+    Static,
+    /// Relocatable object for the host: `<prefix><name>.o`.
+    Object,
     Wasm,
+    /// Shared libraries for the `--cross` triples only.
+    Cross,
+    /// Same as `native` and `wasm`; kept for older configs.
+    #[value(hide = true)]
     All,
 }
 
 impl Target {
     #[must_use]
     pub fn covers(&self, other: Target) -> bool {
-        matches!(
-            (self, other),
-            (Target::All, _) | (Target::Native, Target::Native) | (Target::Wasm, Target::Wasm)
-        )
+        *self == other || (*self == Target::All && matches!(other, Target::Native | Target::Wasm))
+    }
+}
+
+/// What to build, as a set; `All` is expanded.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(from = "OneOrMany<Target>", into = "Vec<Target>")]
+pub struct Targets(BTreeSet<Target>);
+
+impl Targets {
+    #[must_use]
+    pub fn new(targets: &[Target]) -> Self {
+        let mut set = BTreeSet::new();
+        for target in targets {
+            match target {
+                Target::All => set.extend([Target::Native, Target::Wasm]),
+                other => {
+                    set.insert(*other);
+                }
+            }
+        }
+        Self(set)
+    }
+
+    #[must_use]
+    pub fn contains(&self, target: Target) -> bool {
+        self.0.contains(&target)
+    }
+
+    /// Whether `--cross` triples get built: with the host's shared library,
+    /// or on their own.
+    #[must_use]
+    pub fn cross(&self) -> bool {
+        self.contains(Target::Native) || self.contains(Target::Cross)
     }
 
     #[must_use]
     pub fn native(&self) -> bool {
-        matches!(self, Self::All | Self::Native)
+        self.contains(Target::Native)
+    }
+
+    #[must_use]
+    pub fn object(&self) -> bool {
+        self.contains(Target::Object)
+    }
+
+    #[must_use]
+    pub fn static_lib(&self) -> bool {
+        self.contains(Target::Static)
     }
 
     #[must_use]
     pub fn wasm(&self) -> bool {
-        matches!(self, Self::All | Self::Wasm)
+        self.contains(Target::Wasm)
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use clap::ValueEnum;
+
+        let value = self.to_possible_value().expect("every target has a name");
+        write!(f, "{}", value.get_name())
+    }
+}
+
+/// Comma-separated, the way `--target` takes them.
+impl fmt::Display for Targets {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, target) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{target}")?;
+        }
+        Ok(())
+    }
+}
+
+impl From<OneOrMany<Target>> for Targets {
+    fn from(targets: OneOrMany<Target>) -> Self {
+        Self::new(&Vec::from(targets))
+    }
+}
+
+impl From<Targets> for Vec<Target> {
+    fn from(targets: Targets) -> Self {
+        targets.0.into_iter().collect()
+    }
+}
+
+/// A config value that can be written as a single item or a list of them.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+impl<T> From<OneOrMany<T>> for Vec<T> {
+    fn from(value: OneOrMany<T>) -> Self {
+        match value {
+            OneOrMany::One(one) => vec![one],
+            OneOrMany::Many(many) => many,
+        }
+    }
+}
+
+fn one_or_many<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    OneOrMany::deserialize(deserializer).map(Vec::from)
+}
+
+/// Compiler settings for parsers.
+#[derive(
+    clap::ValueEnum, Clone, Copy, Debug, Default, Deserialize, Diff, PartialEq, Eq, Serialize,
+)]
+#[diff(attr(
+    #[derive(Debug, PartialEq)]
+))]
+#[serde(rename_all = "kebab-case")]
+pub enum Profile {
+    /// Optimized, as `tree-sitter build` does it.
+    #[default]
+    Release,
+    /// `-O0 -g`.
+    Debug,
+    /// `-fsanitize=address`, with debug info.
+    Asan,
+    /// `-fsanitize=undefined`, with debug info.
+    Ubsan,
+}
+
+impl Profile {
+    #[must_use]
+    pub fn cflags(&self) -> &'static [&'static str] {
+        match self {
+            Profile::Release => &["-O2"],
+            Profile::Debug => &["-O0", "-g"],
+            Profile::Asan => &["-O1", "-g", "-fno-omit-frame-pointer", "-fsanitize=address"],
+            Profile::Ubsan => &["-O1", "-g", "-fsanitize=undefined"],
+        }
+    }
+
+    #[must_use]
+    pub fn ldflags(&self) -> &'static [&'static str] {
+        match self {
+            Profile::Release | Profile::Debug => &[],
+            Profile::Asan => &["-fsanitize=address"],
+            Profile::Ubsan => &["-fsanitize=undefined"],
+        }
+    }
+
+    /// Subdirectory keeping this profile's artifacts apart; release ones
+    /// stay where they always were.
+    #[must_use]
+    pub fn subdir(&self) -> Option<&'static str> {
+        match self {
+            Profile::Release => None,
+            Profile::Debug => Some("debug"),
+            Profile::Asan => Some("asan"),
+            Profile::Ubsan => Some("ubsan"),
+        }
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use clap::ValueEnum;
+
+        let value = self.to_possible_value().expect("every profile has a name");
+        write!(f, "{}", value.get_name())
+    }
+}
+
+/// What to strip from the host's shared library after building it.
+#[derive(
+    clap::ValueEnum, Clone, Copy, Debug, Default, Deserialize, Diff, PartialEq, Eq, Serialize,
+)]
+#[diff(attr(
+    #[derive(Debug, PartialEq)]
+))]
+#[serde(rename_all = "kebab-case")]
+pub enum Strip {
+    /// Leave it as built.
+    #[default]
+    None,
+    /// Strip everything not needed to load it.
+    All,
+    /// Move debug info to a `.debug` file next to it.
+    Split,
+}
+
+/// How to print reports meant for both people and tools.
+#[derive(
+    clap::ValueEnum, Clone, Copy, Debug, Default, Deserialize, Diff, PartialEq, Eq, Serialize,
+)]
+#[diff(attr(
+    #[derive(Debug, PartialEq)]
+))]
+#[serde(rename_all = "kebab-case")]
+pub enum Format {
+    #[default]
+    Text,
+    Json,
+}
+
+/// How built parsers get from `build-dir` into `out-dir`.
+#[derive(
+    clap::ValueEnum, Clone, Copy, Debug, Default, Deserialize, Diff, PartialEq, Eq, Serialize,
+)]
+#[diff(attr(
+    #[derive(Debug, PartialEq)]
+))]
+#[serde(rename_all = "kebab-case")]
+pub enum InstallMode {
+    /// Hardlink, or copy when `out-dir` is on another filesystem.
+    #[default]
+    Auto,
+    Hardlink,
+    Copy,
+    /// Symlink to `build-dir`; wiping it breaks the installed parsers.
+    Symlink,
+    /// Copy-on-write clone; needs a filesystem that supports it, like btrfs or XFS.
+    Reflink,
+}
+
+/// When staged parsers are moved into `out-dir`.
+#[derive(
+    clap::ValueEnum, Clone, Copy, Debug, Default, Deserialize, Diff, PartialEq, Eq, Serialize,
+)]
+#[diff(attr(
+    #[derive(Debug, PartialEq)]
+))]
+#[serde(rename_all = "kebab-case")]
+pub enum InstallCommit {
+    /// All at once, and only if the whole build succeeds.
+    #[default]
+    Run,
+    /// As soon as each language's grammars all succeed.
+    Language,
+}
+
+/// What `tsdl pack` makes.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    #[default]
+    #[value(name = "tar.zst")]
+    TarZst,
+    #[value(name = "tar.gz")]
+    TarGz,
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// The extension of its archives, which `tsdl unpack` goes by.
+    #[must_use]
+    pub fn ext(self) -> &'static str {
+        match self {
+            Self::TarZst => "tar.zst",
+            Self::TarGz => "tar.gz",
+            Self::Zip => "zip",
+        }
+    }
+
+    #[must_use]
+    pub fn of(archive: &Path) -> Option<Self> {
+        let name = archive.file_name()?.to_string_lossy();
+        [Self::TarZst, Self::TarGz, Self::Zip]
+            .into_iter()
+            .find(|format| name.ends_with(&format!(".{}", format.ext())))
+    }
+}
+
+/// When to run `tree-sitter generate` before building.
+#[derive(
+    clap::ValueEnum, Clone, Copy, Debug, Default, Deserialize, Diff, PartialEq, Eq, Serialize,
+)]
+#[diff(attr(
+    #[derive(Debug, PartialEq)]
+))]
+#[serde(rename_all = "kebab-case")]
+pub enum Generate {
+    /// Always regenerate `src/` from `grammar.js`.
+    #[default]
+    Always,
+    /// Build from the committed `src/` as-is.
+    Never,
+    /// Generate only when `src/parser.c` is not committed.
+    IfMissing,
+}
+
+impl Generate {
+    /// Whether `tree-sitter generate`'s output found in the checkout is an
+    /// input of the build rather than something we overwrite.
+    #[must_use]
+    pub fn uses_committed(&self) -> bool {
+        !matches!(self, Self::Always)
     }
 }
 
@@ -148,11 +505,61 @@ impl Target {
 ))]
 #[serde(rename_all = "kebab-case")]
 pub struct BuildCommand {
+    /// Parser ABI version passed to `tree-sitter generate`; defaults to the CLI's own.
+    #[arg(long)]
+    #[serde(default)]
+    pub abi: Option<u8>,
+
     /// Build Directory.
     #[serde(default)]
     #[arg(short, long, env = "TSDL_BUILD_DIR", default_value = TSDL_BUILD_DIR)]
     pub build_dir: PathBuf,
 
+    /// Number of parsers compiled at once; defaults to --jobs.
+    #[arg(long)]
+    #[serde(default)]
+    pub build_jobs: Option<usize>,
+
+    /// Compiler settings for parsers; non-release builds go to `out-dir/<profile>/`.
+    #[arg(long, value_enum, default_value_t = Profile::default())]
+    #[serde(default)]
+    pub build_profile: Profile,
+
+    /// Also cross-compile for this target triple; repeatable. See `[toolchains.<triple>]`.
+    #[arg(long, value_name = "TRIPLE")]
+    #[serde(default)]
+    pub cross: Vec<String>,
+
+    /// Write a pkg-config file, a `.cmake` package config and a header for each grammar.
+    #[arg(long, default_value_t = false)]
+    #[serde(default)]
+    pub dev_files: bool,
+
+    /// Print what the build would do, without running git or compilers.
+    #[arg(long, default_value_t = false)]
+    #[serde(skip_serializing, skip_deserializing)]
+    pub dry_run: bool,
+
+    /// Stop at the first failure; same as --max-failures 1.
+    #[arg(long, default_value_t = false)]
+    #[serde(default)]
+    pub fail_fast: bool,
+
+    /// Only retry what failed in the last build.
+    #[arg(long, default_value_t = false)]
+    #[serde(skip_serializing, skip_deserializing)]
+    pub failed: bool,
+
+    /// Number of repositories cloned at once; defaults to --jobs.
+    #[arg(long)]
+    #[serde(default)]
+    pub fetch_jobs: Option<usize>,
+
+    /// Output format of --dry-run.
+    #[arg(long, value_enum, default_value_t = Format::default())]
+    #[serde(skip_serializing, skip_deserializing)]
+    pub format: Format,
+
     /// Force clone the repository and rebuild, bypassing cache checks. Overwrites existing binaries.
     #[arg(long, default_value_t = false)]
     #[serde(default)]
@@ -163,6 +570,25 @@ pub struct BuildCommand {
     #[serde(default)]
     pub fresh: bool,
 
+    /// When to run `tree-sitter generate`; `never` builds from the committed `src/`.
+    #[arg(long, value_enum, default_value_t = Generate::default())]
+    #[serde(default)]
+    pub generate: Generate,
+
+    /// Hooks for every parser; parsers can override them one by one.
+    #[clap(skip)]
+    pub hooks: Option<Hooks>,
+
+    /// When to move the parsers staged in `out-dir/.tsdl-staging` into place.
+    #[arg(long, value_enum, default_value_t = InstallCommit::default())]
+    #[serde(default)]
+    pub install_commit: InstallCommit,
+
+    /// How to put parsers into `out-dir`.
+    #[arg(long, value_enum, default_value_t = InstallMode::default())]
+    #[serde(default)]
+    pub install_mode: InstallMode,
+
     /// Parsers to compile.
     #[serde(skip_serializing, skip_deserializing)]
     #[arg(verbatim_doc_comment)]
@@ -173,6 +599,21 @@ pub struct BuildCommand {
     #[serde(default)]
     pub jobs: usize,
 
+    /// JS runtime `tree-sitter generate` evaluates grammars with: node, bun, deno, or a path.
+    #[arg(long, env = "TREE_SITTER_JS_RUNTIME")]
+    #[serde(default)]
+    pub js_runtime: Option<String>,
+
+    /// Stop scheduling builds and cancel the running ones after this many failures.
+    #[arg(long, value_name = "N")]
+    #[serde(default)]
+    pub max_failures: Option<usize>,
+
+    /// Hold new compiles back while the system uses more memory than this, in MB.
+    #[arg(long, value_name = "MB")]
+    #[serde(default)]
+    pub max_memory: Option<u64>,
+
     /// Output Directory.
     #[arg(short, long, env = "TSDL_OUT_DIR", default_value = TSDL_OUT_DIR)]
     #[serde(default)]
@@ -182,19 +623,58 @@ pub struct BuildCommand {
     #[clap(skip)]
     pub parsers: Option<BTreeMap<String, ParserConfig>>,
 
+    /// Where to install each artifact in out-dir, e.g. "{target}/{language}/{prefix}{grammar}.{ext}".
+    #[arg(long, value_name = "TEMPLATE")]
+    #[serde(default)]
+    pub output_template: Option<String>,
+
     /// Prefix parser names.
     #[arg(short, long, env = "TSDL_PREFIX", default_value = TSDL_PREFIX)]
     #[serde(default)]
     pub prefix: String,
 
+    /// Install each grammar's queries (highlights, injections, ...) too.
+    #[arg(long, default_value_t = false)]
+    #[serde(default)]
+    pub queries: bool,
+
+    /// Where queries go in out-dir; "{language}" and "{grammar}" are filled in.
+    #[arg(long, value_name = "TEMPLATE", default_value = TSDL_QUERIES_DIR)]
+    #[serde(default)]
+    pub queries_dir: String,
+
+    /// Fail grammars whose repo has no LICENSE or COPYING file.
+    #[arg(long, default_value_t = false)]
+    #[serde(default)]
+    pub require_license: bool,
+
+    #[command(flatten)]
+    #[serde(default)]
+    pub sandbox: Sandbox,
+
     /// Show Config.
     #[arg(long, default_value_t = TSDL_SHOW_CONFIG)]
     #[serde(default)]
     pub show_config: bool,
 
-    /// Build target.
-    #[arg(short, long, value_enum, default_value_t = Target::default())]
-    pub target: Target,
+    /// Kill any command tsdl runs (clone, generate, compile, scripts, hooks) after this many seconds.
+    #[arg(long, value_name = "SECONDS")]
+    #[serde(default)]
+    pub step_timeout: Option<u64>,
+
+    /// Strip the host's shared library, or split its debug info into a `.debug` file.
+    #[arg(long, value_enum, default_value_t = Strip::default())]
+    #[serde(default)]
+    pub strip: Strip,
+
+    /// What to build; repeatable or comma-separated.
+    #[arg(short, long, value_enum, value_delimiter = ',', default_values_t = [Target::default()])]
+    #[serde(deserialize_with = "one_or_many")]
+    pub target: Vec<Target>,
+
+    /// Toolchains for `--cross`, keyed by target triple.
+    #[clap(skip)]
+    pub toolchains: Option<BTreeMap<String, ToolchainConfig>>,
 
     #[command(flatten)]
     #[serde(default)]
@@ -204,23 +684,54 @@ pub struct BuildCommand {
     #[arg(long, default_value_t = false)]
     #[serde(default)]
     pub unlock: bool,
+
+    /// Install the host's shared library as `<name>.so.<version>`, with a SONAME and symlinks.
+    #[arg(long, default_value_t = false)]
+    #[serde(default)]
+    pub versioned: bool,
 }
 
 impl Default for BuildCommand {
     fn default() -> Self {
         Self {
+            abi: None,
             build_dir: PathBuf::from(TSDL_BUILD_DIR),
+            build_jobs: None,
+            build_profile: Profile::default(),
+            cross: Vec::new(),
+            dev_files: false,
+            dry_run: false,
+            fail_fast: false,
+            failed: false,
+            fetch_jobs: None,
+            format: Format::default(),
             force: TSDL_FORCE,
             fresh: TSDL_FRESH,
+            generate: Generate::default(),
+            hooks: None,
+            install_commit: InstallCommit::default(),
+            install_mode: InstallMode::default(),
             languages: None,
             jobs: num_cpus::get(),
+            js_runtime: None,
+            max_failures: None,
+            max_memory: None,
             out_dir: PathBuf::from(TSDL_OUT_DIR),
+            output_template: None,
             parsers: None,
             prefix: String::from(TSDL_PREFIX),
+            queries: false,
+            queries_dir: String::from(TSDL_QUERIES_DIR),
+            require_license: false,
+            sandbox: Sandbox::default(),
             show_config: TSDL_SHOW_CONFIG,
-            target: Target::default(),
+            step_timeout: None,
+            strip: Strip::default(),
+            target: vec![Target::default()],
+            toolchains: None,
             tree_sitter: TreeSitter::default(),
             unlock: false,
+            versioned: false,
         }
     }
 }
@@ -233,19 +744,173 @@ impl Default for BuildCommand {
 #[serde(rename_all = "kebab-case")]
 pub enum ParserConfig {
     Full {
+        #[serde(default)]
+        #[diff(attr(#[derive(Debug, PartialEq)]))]
+        abi: Option<u8>,
+
         #[serde(alias = "cmd", alias = "script")]
         build_script: Option<String>,
 
         #[diff(attr(#[derive(Debug, PartialEq)]))]
         from: Option<String>,
 
+        #[serde(default)]
+        #[diff(attr(#[derive(Debug, PartialEq)]))]
+        generate: Option<Generate>,
+
+        #[serde(default, rename = "js-runtime", alias = "js_runtime")]
+        #[diff(attr(#[derive(Debug, PartialEq)]))]
+        js_runtime: Option<String>,
+
         #[serde(rename = "ref")]
         #[diff(attr(#[derive(Debug, PartialEq)]))]
         git_ref: String,
+
+        #[serde(default)]
+        #[diff(attr(#[derive(Debug, PartialEq)]))]
+        hooks: Option<Hooks>,
+
+        #[serde(default, rename = "output-template", alias = "output_template")]
+        #[diff(attr(#[derive(Debug, PartialEq)]))]
+        output_template: Option<String>,
+
+        #[serde(default)]
+        #[diff(attr(#[derive(Debug, PartialEq)]))]
+        queries: Option<bool>,
     },
     Ref(String),
 }
 
+/// Shell snippets run around a parser's build, with `TSDL_*` variables
+/// describing it in their environment.
+#[derive(Clone, Debug, Default, Deserialize, Diff, PartialEq, Eq, Serialize)]
+#[diff(attr(
+    #[derive(Debug, PartialEq)]
+))]
+#[serde(rename_all = "kebab-case")]
+pub struct Hooks {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pre_generate: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pre_build: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_build: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_install: Option<String>,
+}
+
+impl Hooks {
+    /// These hooks, falling back to `fallback`'s for those that are unset.
+    #[must_use]
+    pub fn or(&self, fallback: &Hooks) -> Hooks {
+        Hooks {
+            pre_generate: self
+                .pre_generate
+                .clone()
+                .or_else(|| fallback.pre_generate.clone()),
+            pre_build: self
+                .pre_build
+                .clone()
+                .or_else(|| fallback.pre_build.clone()),
+            post_build: self
+                .post_build
+                .clone()
+                .or_else(|| fallback.post_build.clone()),
+            post_install: self
+                .post_install
+                .clone()
+                .or_else(|| fallback.post_install.clone()),
+        }
+    }
+}
+
+/// How to cross-compile for a target triple. Unset fields default to the
+/// `<triple>-gcc` cross toolchain.
+#[derive(Clone, Debug, Default, Deserialize, Diff, PartialEq, Eq, Serialize)]
+#[diff(attr(
+    #[derive(Debug, PartialEq)]
+))]
+#[serde(rename_all = "kebab-case")]
+pub struct ToolchainConfig {
+    /// C compiler and its leading arguments, e.g. `zig cc -target aarch64-linux-gnu`.
+    #[serde(default)]
+    pub cc: Option<String>,
+
+    /// C++ compiler for C++ scanners; derived from `cc` when unset.
+    #[serde(default)]
+    pub cxx: Option<String>,
+
+    /// Passed as `--sysroot`.
+    #[serde(default)]
+    pub sysroot: Option<PathBuf>,
+
+    /// Shared library extension.
+    #[serde(default)]
+    pub ext: Option<String>,
+}
+
+/// How build scripts and hooks get isolated.
+#[derive(
+    clap::ValueEnum, Clone, Copy, Debug, Default, Deserialize, Diff, PartialEq, Eq, Serialize,
+)]
+#[diff(attr(
+    #[derive(Debug, PartialEq)]
+))]
+#[serde(rename_all = "kebab-case")]
+pub enum SandboxMode {
+    /// Run them as is.
+    #[default]
+    Off,
+    /// bubblewrap if installed, user namespaces otherwise.
+    Auto,
+    /// bubblewrap.
+    Bwrap,
+    /// `unshare` and user namespaces.
+    Unshare,
+}
+
+#[derive(clap::Args, Clone, Debug, Diff, Deserialize, PartialEq, Eq, Serialize)]
+#[diff(attr(
+    #[derive(Debug, PartialEq)]
+))]
+#[serde(rename_all = "kebab-case")]
+pub struct Sandbox {
+    /// Sandbox build scripts and hooks: read-only filesystem but for the grammar and /tmp, no network.
+    #[arg(long = "sandbox", value_enum, default_value_t = SandboxMode::default())]
+    #[serde(default)]
+    pub mode: SandboxMode,
+
+    /// Whether build scripts and hooks may run when they can't be sandboxed.
+    #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
+    #[serde(default = "default_true")]
+    pub allow_unsandboxed: bool,
+
+    /// CPU time limit for build scripts and hooks, in seconds.
+    #[arg(long = "sandbox-cpu-seconds")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_seconds: Option<u64>,
+
+    /// Virtual memory limit for build scripts and hooks, in MiB.
+    #[arg(long = "sandbox-memory-mb")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_mb: Option<u64>,
+}
+
+impl Default for Sandbox {
+    fn default() -> Self {
+        Self {
+            mode: SandboxMode::default(),
+            allow_unsandboxed: true,
+            cpu_seconds: None,
+            memory_mb: None,
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
 #[derive(clap::Args, Clone, Debug, Diff, Deserialize, PartialEq, Eq, Serialize)]
 #[diff(attr(
     #[derive(Debug, PartialEq)]
@@ -274,6 +939,15 @@ impl Default for TreeSitter {
     }
 }
 
+#[derive(clap::Subcommand, Clone, Debug)]
+pub enum CacheCommand {
+    /// Show cached grammars, their source files, and what changed since they were built.
+    Show {
+        /// Only show these languages.
+        languages: Vec<String>,
+    },
+}
+
 #[derive(clap::Subcommand, Clone, Debug, Default)]
 pub enum ConfigCommand {
     #[default]
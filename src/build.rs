@@ -1,8 +1,9 @@
 use std::{
-    collections::{BTreeMap, HashSet},
+    collections::{BTreeSet, HashSet},
     fs::{self, create_dir_all},
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::Arc,
+    time::Duration,
 };
 
 use serde::{Deserialize, Serialize};
@@ -12,25 +13,57 @@ use url::Url;
 use crate::{
     actors::{self, CacheActor, DisplayActor, DisplayAddr},
     app::App,
-    args::{ParserConfig, Target, TreeSitter},
+    args::{
+        Generate, Hooks, InstallMode, ParserConfig, Profile, Sandbox, Strip, Target, Targets,
+        TreeSitter,
+    },
     cache::Db,
+    cancel,
+    cc::Toolchain,
     consts::TSDL_FROM,
     display::{self, Progress, ProgressBar, TICK_CHARS},
     error::{self, TsdlError},
+    failed::Failed,
     git::GitRef,
-    lock::{Lock, LockStatus},
+    install::Transaction,
+    layout,
+    limits::Limits,
+    lock::{Lock, LockGuard, LockStatus},
     parser::LanguageBuild,
-    prompt_user, SafeCanonicalize, TsdlResult,
+    plan, prompt_user, sh, SafeCanonicalize, TsdlResult,
 };
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct BuildSpec {
+    #[serde(default)]
+    pub abi: Option<u8>,
     pub build_script: Option<String>,
+    #[serde(default)]
+    pub cross: Vec<Toolchain>,
+    #[serde(default)]
+    pub dev_files: bool,
+    #[serde(default)]
+    pub generate: Generate,
     pub git_ref: GitRef,
+    #[serde(default)]
+    pub hooks: Hooks,
+    #[serde(default)]
+    pub js_runtime: Option<String>,
+    #[serde(default)]
+    pub output_template: Option<String>,
     pub prefix: String,
+    #[serde(default)]
+    pub profile: Profile,
+    /// Where queries get installed, when they do.
+    #[serde(default)]
+    pub queries: Option<String>,
     pub repo: Url,
-    pub target: Target,
+    #[serde(default)]
+    pub strip: Strip,
+    pub target: Targets,
     pub tree_sitter: TreeSitter,
+    #[serde(default)]
+    pub versioned: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -43,7 +76,12 @@ pub struct OutputConfig {
 pub struct BuildContext {
     pub cache_hit: bool,
     pub force: bool,
+    pub install_mode: InstallMode,
     pub progress: Option<display::ProgressBar>,
+    pub require_license: bool,
+    pub sandbox: Arc<Sandbox>,
+    /// How long each command of a grammar's build may run.
+    pub step_timeout: Option<Duration>,
 }
 
 impl BuildContext {
@@ -94,16 +132,36 @@ pub fn run(app: &mut App) -> TsdlResult<()> {
         crate::config::show(&app.command)?;
     }
 
-    // Initialize the manager first with the build directory
-    let lock = Lock::new(&app.command.build_dir);
+    if app.command.dry_run {
+        return plan::run(app);
+    }
+
+    let _guard = lock(app)?;
+
+    clear(app)?;
+    ignite(app)?;
+    Ok(())
+}
+
+/// Take the locks on `build-dir` and `out-dir`, asking what to do when
+/// another process holds one. Everything that writes to `out-dir` holds
+/// them, so configs with their own `build-dir` can still share an `out-dir`.
+pub fn lock(app: &App) -> TsdlResult<Vec<LockGuard>> {
+    let mut guards = vec![lock_dir(app, &app.command.build_dir)?];
+    if app.command.out_dir != app.command.build_dir {
+        guards.push(lock_dir(app, &app.command.out_dir)?);
+    }
+    Ok(guards)
+}
+
+fn lock_dir(app: &App, dir: &Path) -> TsdlResult<LockGuard> {
+    let lock = Lock::new(dir);
 
     if app.command.unlock {
         lock.force_unlock()?;
     }
 
-    // Check lock status before clearing anything
-
-    let _guard = match lock.try_acquire()? {
+    let guard = match lock.try_acquire()? {
         LockStatus::Acquired(lock) => lock,
 
         LockStatus::Cyclic => {
@@ -131,7 +189,7 @@ pub fn run(app: &mut App) -> TsdlResult<()> {
         }
 
         LockStatus::Unknown { pid, reason } => {
-            eprintln!("Could not verify lock owner PID {pid}: {reason}",);
+            eprintln!("Could not verify lock owner PID {pid}: {reason}");
             if prompt_user("Take over lock?", false)? {
                 lock.force_acquire()?
             } else {
@@ -140,9 +198,7 @@ pub fn run(app: &mut App) -> TsdlResult<()> {
         }
     };
 
-    clear(app)?;
-    ignite(app)?;
-    Ok(())
+    Ok(guard)
 }
 
 fn clear(app: &mut App) -> TsdlResult<()> {
@@ -157,8 +213,20 @@ fn clear(app: &mut App) -> TsdlResult<()> {
     Ok(())
 }
 
-fn collect_languages(app: &App) -> Result<Vec<LanguageBuild>, error::LanguageCollection> {
-    let results = unique_languages(app);
+/// The failures of the last run when `--failed` asks to retry them.
+pub(crate) fn retried_failures(app: &App) -> TsdlResult<Option<Failed>> {
+    if app.command.failed {
+        Failed::load(&app.command.build_dir).map(Some)
+    } else {
+        Ok(None)
+    }
+}
+
+pub(crate) fn collect_languages(
+    app: &App,
+    failed: Option<&Failed>,
+) -> Result<Vec<LanguageBuild>, error::LanguageCollection> {
+    let results = unique_languages(app, failed);
     let (ok, err): (Vec<_>, Vec<_>) = results.into_iter().partition(Result::is_ok);
 
     if err.is_empty() {
@@ -170,41 +238,110 @@ fn collect_languages(app: &App) -> Result<Vec<LanguageBuild>, error::LanguageCol
     }
 }
 
+fn cross_toolchains(app: &App) -> Vec<Toolchain> {
+    let toolchains = app.command.toolchains.as_ref();
+    app.command
+        .cross
+        .iter()
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .map(|triple| Toolchain::resolve(triple, toolchains.and_then(|t| t.get(triple))))
+        .collect()
+}
+
 fn default_repo(language: &str) -> TsdlResult<Url> {
     let url = format!("{TSDL_FROM}{language}");
     Url::parse(&url)
         .map_err(|e| TsdlError::context(format!("Creating url {url} for {language}"), e))
 }
 
-fn get_language_coords(
-    language: &str,
-    defined_parsers: Option<&BTreeMap<String, ParserConfig>>,
-) -> (Option<String>, GitRef, TsdlResult<Url>) {
+fn language_spec(app: &App, language: &str) -> TsdlResult<BuildSpec> {
     // Attempt to find the config; defaults to None if map or key is missing
-    let config = defined_parsers.and_then(|parsers| parsers.get(language));
+    let config = app
+        .command
+        .parsers
+        .as_ref()
+        .and_then(|parsers| parsers.get(language));
+
+    let mut spec = BuildSpec {
+        abi: app.command.abi,
+        build_script: None,
+        cross: cross_toolchains(app),
+        dev_files: app.command.dev_files,
+        generate: app.command.generate,
+        git_ref: GitRef::from("HEAD"),
+        hooks: app.command.hooks.clone().unwrap_or_default(),
+        js_runtime: app.command.js_runtime.clone(),
+        output_template: app.command.output_template.clone(),
+        prefix: app.command.prefix.clone(),
+        profile: app.command.build_profile,
+        queries: None,
+        repo: default_repo(language)?,
+        strip: app.command.strip,
+        target: Targets::new(&app.command.target),
+        tree_sitter: app.command.tree_sitter.clone(),
+        versioned: app.command.versioned,
+    };
 
+    let mut queries = app.command.queries;
     match config {
-        Some(ParserConfig::Ref(git_ref)) => {
-            (None, resolve_git_ref(git_ref), default_repo(language))
-        }
+        Some(ParserConfig::Ref(git_ref)) => spec.git_ref = resolve_git_ref(git_ref),
 
         Some(ParserConfig::Full {
+            abi,
             build_script,
-            git_ref,
             from,
+            generate,
+            git_ref,
+            hooks,
+            js_runtime,
+            output_template,
+            queries: with_queries,
         }) => {
-            let url_result = match from {
-                Some(url_str) => Url::parse(url_str).map_err(|e| {
+            if let Some(url_str) = from {
+                spec.repo = Url::parse(url_str).map_err(|e| {
                     TsdlError::context(format!("Parsing {url_str} for {language}"), e)
-                }),
-                None => default_repo(language),
-            };
-
-            (build_script.clone(), resolve_git_ref(git_ref), url_result)
+                })?;
+            }
+            spec.abi = abi.or(spec.abi);
+            spec.build_script.clone_from(build_script);
+            spec.generate = generate.unwrap_or(spec.generate);
+            spec.git_ref = resolve_git_ref(git_ref);
+            if let Some(hooks) = hooks {
+                spec.hooks = hooks.or(&spec.hooks);
+            }
+            if js_runtime.is_some() {
+                spec.js_runtime.clone_from(js_runtime);
+            }
+            if output_template.is_some() {
+                spec.output_template.clone_from(output_template);
+            }
+            queries = with_queries.unwrap_or(queries);
         }
 
-        None => (None, GitRef::from("HEAD"), default_repo(language)),
+        None => {}
+    }
+
+    // Static libraries, objects and cross builds are compiled by tsdl, which
+    // would silently bypass the script.
+    let compiled = spec.target.static_lib()
+        || spec.target.object()
+        || (spec.target.cross() && !spec.cross.is_empty());
+    if spec.build_script.is_some() && compiled {
+        return Err(TsdlError::message(
+            "A build script (`cmd`) only supports the native and wasm targets",
+        ));
     }
+
+    if let Some(template) = &spec.output_template {
+        layout::validate("output-template", template)?;
+    }
+    if queries {
+        layout::validate("queries-dir", &app.command.queries_dir)?;
+        spec.queries = Some(app.command.queries_dir.clone());
+    }
+
+    Ok(spec)
 }
 
 fn ignite(app: &App) -> TsdlResult<()> {
@@ -217,12 +354,29 @@ fn ignite(app: &App) -> TsdlResult<()> {
     let guard = rt.enter();
 
     let db = Db::load(&app.command.build_dir)?;
-    let languages = collect_languages(app)?;
+    let failed = retried_failures(app)?;
+    if failed.as_ref().is_some_and(Failed::is_empty) {
+        println!("No failures from the last run to retry");
+        return Ok(());
+    }
+    let languages = collect_languages(app, failed.as_ref())?;
+    preflight(app)?;
 
     let result = rt.block_on(async move {
+        cancel::listen().map_err(|e| TsdlError::context("Installing signal handlers", e))?;
         let cache = CacheActor::spawn(db, app.command.force);
         let display = DisplayActor::spawn(Progress::new(app.progress.mode));
 
+        if let Some(failed) = &failed {
+            let keys = failed.keys.iter().cloned().collect::<Vec<_>>();
+            display
+                .println(format!(
+                    "Retrying failures from the last run: {}",
+                    keys.join(", ")
+                ))
+                .await;
+        }
+
         let display2 = display.clone();
         tokio::spawn(async {
             update_screen(display2).await;
@@ -232,8 +386,9 @@ fn ignite(app: &App) -> TsdlResult<()> {
             &app.command.build_dir,
             cache,
             display,
-            app.command.jobs,
+            &Limits::new(&app.command),
             languages,
+            Transaction::new(&app.command.out_dir.canon()?, app.command.install_commit)?,
             &app.command.tree_sitter,
         )
         .await?;
@@ -246,6 +401,32 @@ fn ignite(app: &App) -> TsdlResult<()> {
     result
 }
 
+/// Fail early on missing tools instead of in the middle of a build.
+fn preflight(app: &App) -> TsdlResult<()> {
+    let targets = Targets::new(&app.command.target);
+
+    if targets.contains(Target::Cross) && app.command.cross.is_empty() {
+        return Err(TsdlError::message(
+            "--target cross needs at least one --cross <triple>",
+        ));
+    }
+
+    if targets.cross() {
+        for toolchain in cross_toolchains(app) {
+            let program = toolchain.program();
+            if sh::which(program).is_none() {
+                return Err(TsdlError::message(format!(
+                    "`{program}` is needed to cross-compile for {} but was not found in PATH; \
+                     configure [toolchains.{}]",
+                    toolchain.triple, toolchain.triple
+                )));
+            }
+        }
+    }
+
+    Ok(())
+}
+
 fn resolve_git_ref(git_ref: &str) -> GitRef {
     let is_sha1 = git_ref.len() == 40 && git_ref.chars().all(|c| c.is_ascii_hexdigit());
 
@@ -260,11 +441,17 @@ fn resolve_git_ref(git_ref: &str) -> GitRef {
     }
 }
 
-fn unique_languages(app: &App) -> Vec<Result<LanguageBuild, error::Language>> {
+fn unique_languages(
+    app: &App,
+    failed: Option<&Failed>,
+) -> Vec<Result<LanguageBuild, error::Language>> {
     let requested_languages = &app.command.languages;
     let defined_parsers = app.command.parsers.as_ref();
 
     let final_languages = match requested_languages {
+        _ if failed.is_some() => failed
+            .map(|failed| failed.languages().into_iter().collect())
+            .unwrap_or_default(),
         Some(langs) if !langs.is_empty() => langs.clone(),
         _ => defined_parsers
             .map(|parsers| parsers.keys().cloned().collect())
@@ -275,22 +462,19 @@ fn unique_languages(app: &App) -> Vec<Result<LanguageBuild, error::Language>> {
     let mut results = Vec::new();
 
     for language in unique {
-        let (build_script, git_ref, url) = get_language_coords(&language, defined_parsers);
-        let result = match url {
-            Ok(repo) => Ok(LanguageBuild::new(
+        let grammars = failed.and_then(|failed| failed.grammars(&language));
+        let result = match language_spec(app, &language) {
+            Ok(spec) => Ok(LanguageBuild::new(
                 BuildContext {
                     force: app.command.force || app.command.fresh,
                     cache_hit: false,
+                    install_mode: app.command.install_mode,
                     progress: None, // Progress is handled by DisplayActor
+                    require_license: app.command.require_license,
+                    sandbox: Arc::new(app.command.sandbox.clone()),
+                    step_timeout: app.command.step_timeout.map(Duration::from_secs),
                 },
-                Arc::new(BuildSpec {
-                    build_script,
-                    git_ref,
-                    repo,
-                    tree_sitter: app.command.tree_sitter.clone(),
-                    prefix: app.command.prefix.clone(),
-                    target: app.command.target,
-                }),
+                Arc::new(spec),
                 language.clone().into(),
                 OutputConfig {
                     build_dir: app
@@ -307,7 +491,8 @@ fn unique_languages(app: &App) -> Vec<Result<LanguageBuild, error::Language>> {
                         .expect("Out dir canonicalization failed")
                         .into(),
                 },
-            )),
+            )
+            .only(grammars)),
             Err(err) => Err(error::Language::new(language, err)),
         };
         results.push(result);
@@ -1,16 +1,21 @@
 use std::{
     collections::BTreeMap,
+    fmt::{self, Write as _},
     fs,
     path::{Path, PathBuf},
     sync::Arc,
 };
 
+use derive_more::Deref;
 use serde::{Deserialize, Serialize};
 use sha1::{Digest, Sha1};
 use tokio::io::{AsyncReadExt, ReadBuf};
 use tracing::debug;
 
-use crate::{build::BuildSpec, consts::TSDL_CACHE_FILE, error::TsdlError, TsdlResult};
+use crate::{
+    app::App, args::CacheCommand, build::BuildSpec, consts::TSDL_CACHE_FILE, error::TsdlError,
+    parser, SafeCanonicalize, TsdlResult,
+};
 
 /// The build cache stored in  `<build-dir>/<TSDL_CACHE_FILE>`
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
@@ -22,12 +27,82 @@ pub struct Db {
 /// Cache entry for a single parser
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Entry {
-    /// Hash of the grammar.js file(s)
+    /// Combined hash of all the grammar's source files
     pub hash: Arc<str>,
+    /// The files behind `hash`, so we can tell what changed
+    #[serde(default)]
+    pub files: Sources,
     /// Complete build definition that affects parser output
     pub spec: Arc<BuildSpec>,
 }
 
+/// Hashes of the files that make up a grammar, keyed by their path relative
+/// to the repo root.
+#[derive(Debug, Clone, Default, Deref, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Sources(BTreeMap<String, String>);
+
+/// How a source file differs from the one used in the cached build
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Added(String),
+    Modified(String),
+    Removed(String),
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Added(path) => write!(f, "added {path}"),
+            Change::Modified(path) => write!(f, "modified {path}"),
+            Change::Removed(path) => write!(f, "removed {path}"),
+        }
+    }
+}
+
+impl Sources {
+    /// Files added, modified or removed since `old`.
+    #[must_use]
+    pub fn changes(&self, old: &Sources) -> Vec<Change> {
+        let mut changes = Vec::new();
+
+        for (path, hash) in &self.0 {
+            match old.get(path) {
+                None => changes.push(Change::Added(path.clone())),
+                Some(old_hash) if old_hash != hash => changes.push(Change::Modified(path.clone())),
+                Some(_) => {}
+            }
+        }
+
+        for path in old.keys() {
+            if !self.contains_key(path) {
+                changes.push(Change::Removed(path.clone()));
+            }
+        }
+
+        changes
+    }
+
+    /// Combined hash of all files, used as the grammar's cache key.
+    #[must_use]
+    pub fn hash(&self) -> String {
+        let mut hasher = Sha1::new();
+
+        for (path, hash) in &self.0 {
+            hasher.update(path.as_bytes());
+            hasher.update(b"\0");
+            hasher.update(hash.as_bytes());
+            hasher.update(b"\n");
+        }
+
+        format!("{:x}", hasher.finalize())
+    }
+
+    pub fn insert(&mut self, path: String, hash: String) {
+        self.0.insert(path, hash);
+    }
+}
+
 /// Represents a "Delta" to be applied to the cache after a successful build
 #[derive(Debug, Clone)]
 pub struct Update {
@@ -81,6 +156,16 @@ impl Db {
             .map_err(|e| TsdlError::context(format!("Parsing cache file at {}", file.display()), e))
     }
 
+    /// Whether `language`'s checkout is missing from the cache or was made
+    /// for another spec.
+    #[must_use]
+    pub fn needs_clone(&self, language: &str, spec: &BuildSpec) -> bool {
+        self.parsers
+            .iter()
+            .find(|(key, _)| key.starts_with(&format!("{language}/")))
+            .is_none_or(|(_, entry)| entry.spec.as_ref() != spec)
+    }
+
     /// Check if a parser needs rebuilding by comparing grammar hash and build definition
     pub fn needs_rebuild(&self, name: &str, hash: &str, spec: &BuildSpec) -> bool {
         // TODO: hash and name are plain str, I'd like strong types here.
@@ -127,13 +212,88 @@ impl Db {
     }
 }
 
-/// Hash the contents of a file using SHA-1 and return the hex string.
-pub async fn hash_file(path: &Path) -> TsdlResult<String> {
+pub fn run(app: &App, command: &CacheCommand) -> TsdlResult<()> {
+    match command {
+        CacheCommand::Show { languages } => show(app, languages),
+    }
+}
+
+/// Print the cached grammars and how their sources differ from what's
+/// currently checked out in the build directory.
+fn show(app: &App, languages: &[String]) -> TsdlResult<()> {
+    let db = Db::load(&app.command.build_dir)?;
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+
+    let mut current = BTreeMap::new();
+
+    for (key, entry) in &db.parsers {
+        let Some((language, grammar)) = key.split_once('/') else {
+            continue;
+        };
+
+        if !languages.is_empty() && !languages.iter().any(|l| l == language) {
+            continue;
+        }
+
+        if !current.contains_key(language) {
+            let dir = app
+                .command
+                .build_dir
+                .join(format!("tree-sitter-{language}"))
+                .canon()?;
+            let grammars = if dir.exists() {
+                rt.block_on(parser::discover(
+                    dir.into(),
+                    entry.spec.generate,
+                    entry.spec.queries.is_some(),
+                ))
+                .unwrap_or_default()
+            } else {
+                Vec::new()
+            };
+            current.insert(language.to_string(), grammars);
+        }
+
+        println!("{key} {} {}", entry.spec.git_ref, short(&entry.hash));
+        for (path, hash) in entry.files.iter() {
+            println!("  {path} {}", short(hash));
+        }
+
+        let checkout = current
+            .get(language)
+            .and_then(|grammars| grammars.iter().find(|(name, _, _)| name == grammar));
+
+        match checkout {
+            None => println!("  not checked out"),
+            Some((_, _, sources)) => {
+                let changes = sources.changes(&entry.files);
+                if changes.is_empty() {
+                    println!("  up to date");
+                }
+                for change in changes {
+                    println!("  {change}");
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn short(hash: &str) -> &str {
+    &hash[..hash.len().min(7)]
+}
+
+/// Hash the contents of a file with `D`, SHA-1 for the cache or SHA-256 for
+/// the manifest, and return the hex string.
+pub async fn hash_file<D: Digest>(path: &Path) -> TsdlResult<String> {
     let mut file = tokio::fs::File::open(path).await.map_err(|e| {
         TsdlError::context(format!("Opening file for hashing: {}", path.display()), e)
     })?;
 
-    let mut hasher = Sha1::new();
+    let mut hasher = D::new();
     let mut buffer = vec![0u8; 8192];
 
     loop {
@@ -149,116 +309,128 @@ pub async fn hash_file(path: &Path) -> TsdlResult<String> {
         hasher.update(read_buf.filled());
     }
 
-    let result = hasher.finalize();
-    Ok(format!("{result:x}"))
+    Ok(hasher
+        .finalize()
+        .iter()
+        .fold(String::new(), |mut hex, byte| {
+            _ = write!(hex, "{byte:02x}");
+            hex
+        }))
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
-    use crate::args::{Target, TreeSitter};
+    use crate::args::{Generate, Hooks, Profile, Strip, Target, Targets, TreeSitter};
     use crate::git::GitRef;
 
-    #[test]
-    fn test_needs_rebuild_no_entry() {
-        let cache = Db::default();
-        let test_definition = BuildSpec {
+    /// A spec for tests to override with `..spec()`.
+    pub(crate) fn spec() -> BuildSpec {
+        BuildSpec {
+            abi: None,
             build_script: None,
+            cross: Vec::new(),
+            dev_files: false,
+            generate: Generate::default(),
             git_ref: GitRef::from("master"),
+            hooks: Hooks::default(),
+            js_runtime: None,
+            output_template: None,
             repo: "https://github.com/example/parser".parse().unwrap(),
             tree_sitter: TreeSitter::default(),
             prefix: String::new(),
-            target: Target::Native,
-        };
-        assert!(cache.needs_rebuild("test-parser", "abc123", &test_definition));
+            profile: Profile::default(),
+            queries: None,
+            strip: Strip::default(),
+            target: Targets::new(&[Target::Native]),
+            versioned: false,
+        }
+    }
+
+    #[test]
+    fn test_needs_rebuild_no_entry() {
+        let cache = Db::default();
+        assert!(cache.needs_rebuild("test-parser", "abc123", &spec()));
     }
 
     #[test]
     fn test_needs_rebuild_sha1_mismatch() {
         let mut cache = Db::default();
-        let spec = BuildSpec {
-            build_script: None,
-            git_ref: GitRef::from("master"),
-            repo: "https://github.com/example/parser".parse().unwrap(),
-            tree_sitter: TreeSitter::default(),
-            prefix: String::new(),
-            target: Target::All,
+        let cached = BuildSpec {
+            target: Targets::new(&[Target::All]),
+            ..spec()
         };
         cache.set(
             "test-parser".to_string(),
             Entry {
                 hash: "abc123".into(),
-                spec: spec.into(),
+                files: Sources::default(),
+                spec: cached.into(),
             },
         );
 
-        let current_definition = BuildSpec {
-            build_script: None,
-            git_ref: GitRef::from("master"),
-            repo: "https://github.com/example/parser".parse().unwrap(),
-            tree_sitter: TreeSitter::default(),
-            prefix: String::new(),
-            target: Target::Native,
-        };
-        assert!(cache.needs_rebuild("test-parser", "def456", &current_definition));
+        assert!(cache.needs_rebuild("test-parser", "def456", &spec()));
     }
 
     #[test]
     fn test_needs_rebuild_git_ref_mismatch() {
         let mut cache = Db::default();
-        let spec = BuildSpec {
-            build_script: None,
-            git_ref: GitRef::from("master"),
-            repo: "https://github.com/example/parser".parse().unwrap(),
-            tree_sitter: TreeSitter::default(),
-            prefix: String::new(),
-            target: Target::All,
+        let cached = BuildSpec {
+            target: Targets::new(&[Target::All]),
+            ..spec()
         };
         cache.set(
             "test-parser".to_string(),
             Entry {
                 hash: "abc123".into(),
-                spec: spec.into(),
+                files: Sources::default(),
+                spec: cached.into(),
             },
         );
 
         let current_definition = BuildSpec {
-            build_script: None,
             git_ref: GitRef::from("v1.0.0"),
-            repo: "https://github.com/example/parser".parse().unwrap(),
-            tree_sitter: TreeSitter::default(),
-            prefix: String::new(),
-            target: Target::Native,
+            ..spec()
         };
         assert!(cache.needs_rebuild("test-parser", "abc123", &current_definition));
     }
 
     #[test]
-    fn test_needs_rebuild_target_not_covered() {
+    fn test_needs_rebuild_abi_mismatch() {
         let mut cache = Db::default();
-        let spec = BuildSpec {
-            build_script: None,
-            git_ref: GitRef::from("master"),
-            repo: "https://github.com/example/parser".parse().unwrap(),
-            tree_sitter: TreeSitter::default(),
-            prefix: String::new(),
-            target: Target::Native,
+        let cached = spec();
+        cache.set(
+            "test-parser".to_string(),
+            Entry {
+                hash: "abc123".into(),
+                files: Sources::default(),
+                spec: cached.clone().into(),
+            },
+        );
+
+        let current_definition = BuildSpec {
+            abi: Some(14),
+            ..cached
         };
+        assert!(cache.needs_rebuild("test-parser", "abc123", &current_definition));
+    }
+
+    #[test]
+    fn test_needs_rebuild_target_not_covered() {
+        let mut cache = Db::default();
+        let cached = spec();
         cache.set(
             "test-parser".to_string(),
             Entry {
                 hash: "abc123".into(),
-                spec: spec.into(),
+                files: Sources::default(),
+                spec: cached.into(),
             },
         );
 
         let current_definition = BuildSpec {
-            build_script: None,
-            git_ref: GitRef::from("master"),
-            repo: "https://github.com/example/parser".parse().unwrap(),
-            tree_sitter: TreeSitter::default(),
-            prefix: String::new(),
-            target: Target::Wasm,
+            target: Targets::new(&[Target::Wasm]),
+            ..spec()
         };
         assert!(cache.needs_rebuild("test-parser", "abc123", &current_definition));
     }
@@ -266,22 +438,59 @@ mod tests {
     #[test]
     fn test_needs_rebuild_cache_hit_exact() {
         let mut cache = Db::default();
-        let test_definition = BuildSpec {
-            build_script: None,
-            git_ref: GitRef::from("master"),
-            repo: "https://github.com/example/parser".parse().unwrap(),
-            tree_sitter: TreeSitter::default(),
-            prefix: String::new(),
-            target: Target::Native,
-        };
+        let test_definition = spec();
         cache.set(
             "test-parser".to_string(),
             Entry {
                 hash: "abc123".into(),
+                files: Sources::default(),
                 spec: Arc::new(test_definition.clone()),
             },
         );
 
         assert!(!cache.needs_rebuild("test-parser", "abc123", &test_definition));
     }
+
+    fn sources(files: &[(&str, &str)]) -> Sources {
+        let mut sources = Sources::default();
+        for (path, hash) in files {
+            sources.insert((*path).to_string(), (*hash).to_string());
+        }
+        sources
+    }
+
+    #[test]
+    fn test_sources_hash_covers_every_file() {
+        let grammar = sources(&[("grammar.js", "aaa")]);
+        let with_scanner = sources(&[("grammar.js", "aaa"), ("src/scanner.c", "bbb")]);
+        let scanner_changed = sources(&[("grammar.js", "aaa"), ("src/scanner.c", "ccc")]);
+
+        assert_eq!(grammar.hash(), sources(&[("grammar.js", "aaa")]).hash());
+        assert_ne!(grammar.hash(), with_scanner.hash());
+        assert_ne!(with_scanner.hash(), scanner_changed.hash());
+    }
+
+    #[test]
+    fn test_sources_changes() {
+        let old = sources(&[
+            ("grammar.js", "aaa"),
+            ("src/scanner.c", "bbb"),
+            ("src/tag.h", "ccc"),
+        ]);
+        let new = sources(&[
+            ("common/define-grammar.js", "ddd"),
+            ("grammar.js", "aaa"),
+            ("src/scanner.c", "eee"),
+        ]);
+
+        assert_eq!(
+            new.changes(&old),
+            vec![
+                Change::Added("common/define-grammar.js".to_string()),
+                Change::Modified("src/scanner.c".to_string()),
+                Change::Removed("src/tag.h".to_string()),
+            ]
+        );
+        assert!(old.changes(&old).is_empty());
+    }
 }
@@ -0,0 +1,70 @@
+//! Cancelling a build on SIGINT/SIGTERM: running commands are killed, and
+//! the pipeline stops taking new work so the cache can still be saved.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::Notify,
+};
+use tracing::warn;
+
+use crate::{lock, sh};
+
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+static NOTIFY: Notify = Notify::const_new();
+
+/// Exit code for a second signal, which doesn't wait for the cleanup beyond
+/// releasing the locks.
+const INTERRUPTED: i32 = 130;
+
+/// Cancel everything waiting on [`cancelled`], and kill the commands that are
+/// running: the pipeline stops polling them, so they can't kill themselves.
+pub fn cancel() {
+    CANCELLED.store(true, Ordering::SeqCst);
+    NOTIFY.notify_waiters();
+    sh::kill_all();
+}
+
+#[must_use]
+pub fn is_cancelled() -> bool {
+    CANCELLED.load(Ordering::SeqCst)
+}
+
+/// Resolves once the build is cancelled.
+pub async fn cancelled() {
+    let notified = NOTIFY.notified();
+    if is_cancelled() {
+        return;
+    }
+    notified.await;
+}
+
+/// Cancel on the first SIGINT or SIGTERM, and exit right away on the second,
+/// releasing the locks `exit` won't drop.
+///
+/// # Errors
+///
+/// Will return `Err` if the signal handlers can't be installed.
+pub fn listen() -> std::io::Result<()> {
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = interrupt.recv() => {}
+                _ = terminate.recv() => {}
+            }
+            if is_cancelled() {
+                sh::kill_all();
+                lock::release_all();
+                std::process::exit(INTERRUPTED);
+            }
+            warn!("Cancelling; press Ctrl-C again to exit right away");
+            cancel();
+        }
+    });
+
+    Ok(())
+}
@@ -0,0 +1,384 @@
+//! Compiling parsers straight from a grammar's `src/` with a C toolchain,
+//! for when `tree-sitter build` can't do it: cross-compiling to other
+//! target triples, static libraries and objects, debug profiles and
+//! versioned libraries. Build scripts are never bypassed: their parsers
+//! are refused these targets.
+
+use std::{
+    env::{self, consts::DLL_EXTENSION},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{fs, process::Command};
+
+use crate::{
+    args::{Profile, Strip, ToolchainConfig},
+    error::TsdlError,
+    sh::Exec,
+    TsdlResult,
+};
+
+/// A resolved toolchain for one target triple.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct Toolchain {
+    pub triple: String,
+    pub cc: String,
+    pub cxx: String,
+    pub sysroot: Option<PathBuf>,
+    pub ext: String,
+}
+
+impl Toolchain {
+    /// Fill in what `config` leaves out with the conventional
+    /// `<triple>-gcc`/`<triple>-g++` cross toolchain.
+    #[must_use]
+    pub fn resolve(triple: &str, config: Option<&ToolchainConfig>) -> Self {
+        let config = config.cloned().unwrap_or_default();
+        let cc = config.cc.unwrap_or_else(|| format!("{triple}-gcc"));
+        let cxx = config.cxx.unwrap_or_else(|| cxx_for(&cc));
+
+        Self {
+            triple: triple.to_string(),
+            cc,
+            cxx,
+            sysroot: config.sysroot,
+            ext: config
+                .ext
+                .unwrap_or_else(|| default_ext(triple).to_string()),
+        }
+    }
+
+    /// The host's compilers: `$CC` and `$CXX`, or `cc` and `c++`.
+    #[must_use]
+    pub fn host() -> Self {
+        Self {
+            triple: String::from("host"),
+            cc: env::var("CC").unwrap_or_else(|_| String::from("cc")),
+            cxx: env::var("CXX").unwrap_or_else(|_| String::from("c++")),
+            sysroot: None,
+            ext: DLL_EXTENSION.to_string(),
+        }
+    }
+
+    /// The compiler's executable, without its leading arguments.
+    #[must_use]
+    pub fn program(&self) -> &str {
+        self.cc.split_whitespace().next().unwrap_or_default()
+    }
+
+    fn command(&self, compiler: &str) -> Command {
+        let mut words = compiler.split_whitespace();
+        let mut cmd = Command::new(words.next().unwrap_or_default());
+        cmd.args(words);
+        if let Some(sysroot) = &self.sysroot {
+            cmd.arg(format!("--sysroot={}", sysroot.display()));
+        }
+        cmd
+    }
+}
+
+/// The host's archiver: `$AR`, or `ar`.
+#[must_use]
+pub fn host_ar() -> String {
+    env::var("AR").unwrap_or_else(|_| String::from("ar"))
+}
+
+/// Compile the parser in `dir/src` into the shared library `output`,
+/// with a `DT_SONAME` when there's a `soname`. Objects are left next to
+/// `output`. Each command is killed after `timeout`.
+pub async fn compile(
+    toolchain: &Toolchain,
+    profile: Profile,
+    dir: &Path,
+    output: &Path,
+    soname: Option<&str>,
+    timeout: Option<Duration>,
+) -> TsdlResult<()> {
+    let objects = objects(toolchain, profile, dir, output, timeout).await?;
+
+    let mut cmd = toolchain.command(toolchain.linker(&objects));
+    cmd.arg("-shared").args(profile.ldflags());
+    if let Some(soname) = soname {
+        cmd.arg(format!("-Wl,-soname,{soname}"));
+    }
+    cmd.args(&objects.paths)
+        .arg("-o")
+        .arg(output)
+        .current_dir(dir)
+        .exec_with(timeout)
+        .await?;
+
+    Ok(())
+}
+
+/// Compile the parser in `dir/src` into the static library `output` with
+/// `ar`. Returns whether it has a C++ scanner, in which case whoever links
+/// it also needs the C++ runtime.
+pub async fn archive(
+    toolchain: &Toolchain,
+    ar: &str,
+    profile: Profile,
+    dir: &Path,
+    output: &Path,
+    timeout: Option<Duration>,
+) -> TsdlResult<bool> {
+    let objects = objects(toolchain, profile, dir, output, timeout).await?;
+
+    remove_if_exists(output).await?;
+    let mut words = ar.split_whitespace();
+    Command::new(words.next().unwrap_or("ar"))
+        .args(words)
+        .arg("rcs")
+        .arg(output)
+        .args(&objects.paths)
+        .current_dir(dir)
+        .exec_with(timeout)
+        .await?;
+
+    Ok(objects.cxx)
+}
+
+/// Compile the parser in `dir/src` into the single relocatable object
+/// `output`. Returns whether it has a C++ scanner.
+pub async fn relocatable(
+    toolchain: &Toolchain,
+    profile: Profile,
+    dir: &Path,
+    output: &Path,
+    timeout: Option<Duration>,
+) -> TsdlResult<bool> {
+    let objects = objects(toolchain, profile, dir, output, timeout).await?;
+
+    toolchain
+        .command(toolchain.linker(&objects))
+        .args(["-r", "-nostdlib"])
+        .args(&objects.paths)
+        .arg("-o")
+        .arg(output)
+        .current_dir(dir)
+        .exec_with(timeout)
+        .await?;
+
+    Ok(objects.cxx)
+}
+
+/// The host's `$OBJCOPY`, or `objcopy`.
+#[must_use]
+pub fn host_objcopy() -> String {
+    env::var("OBJCOPY").unwrap_or_else(|_| String::from("objcopy"))
+}
+
+/// Strip the shared library `lib` in place. With [`Strip::Split`], its debug
+/// info goes to `lib.debug`, which `lib` then points to with a debuglink.
+pub async fn strip(
+    objcopy: &str,
+    strip: Strip,
+    lib: &Path,
+    timeout: Option<Duration>,
+) -> TsdlResult<()> {
+    let dir = lib.parent().unwrap_or_else(|| Path::new("."));
+    let objcopy = || {
+        let mut words = objcopy.split_whitespace();
+        let mut cmd = Command::new(words.next().unwrap_or("objcopy"));
+        cmd.args(words).current_dir(dir);
+        cmd
+    };
+
+    match strip {
+        Strip::None => {}
+        Strip::All => {
+            objcopy()
+                .arg("--strip-unneeded")
+                .arg(lib)
+                .exec_with(timeout)
+                .await?;
+        }
+        Strip::Split => {
+            let debug = debug_file(lib);
+            objcopy()
+                .arg("--only-keep-debug")
+                .arg(lib)
+                .arg(&debug)
+                .exec_with(timeout)
+                .await?;
+            objcopy()
+                .arg("--strip-debug")
+                .arg(format!("--add-gnu-debuglink={}", debug.display()))
+                .arg(lib)
+                .exec_with(timeout)
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Where [`Strip::Split`] puts the debug info of `lib`.
+#[must_use]
+pub fn debug_file(lib: &Path) -> PathBuf {
+    let mut name = lib.as_os_str().to_owned();
+    name.push(".debug");
+    PathBuf::from(name)
+}
+
+struct Objects {
+    paths: Vec<PathBuf>,
+    cxx: bool,
+}
+
+impl Toolchain {
+    fn linker(&self, objects: &Objects) -> &str {
+        if objects.cxx {
+            &self.cxx
+        } else {
+            &self.cc
+        }
+    }
+}
+
+/// Compile each source in `dir/src` to an object in `output`'s directory.
+async fn objects(
+    toolchain: &Toolchain,
+    profile: Profile,
+    dir: &Path,
+    output: &Path,
+    timeout: Option<Duration>,
+) -> TsdlResult<Objects> {
+    let src = dir.join("src");
+    let obj_dir = output.parent().unwrap_or(dir);
+    fs::create_dir_all(obj_dir)
+        .await
+        .map_err(|e| TsdlError::context(format!("Creating {}", obj_dir.display()), e))?;
+
+    let mut objects = Objects {
+        paths: Vec::new(),
+        cxx: false,
+    };
+
+    for source in sources(&src).await {
+        let cxx = is_cxx(&source);
+        objects.cxx |= cxx;
+
+        let file_name = source.file_name().unwrap_or_default().to_string_lossy();
+        let object = obj_dir.join(format!("{file_name}.o"));
+        let mut cmd = toolchain.command(if cxx { &toolchain.cxx } else { &toolchain.cc });
+        if !cxx {
+            cmd.arg("-std=c11");
+        }
+        cmd.args(profile.cflags())
+            .args(["-fPIC", "-c", "-I"])
+            .arg(&src)
+            .arg(&source)
+            .arg("-o")
+            .arg(&object)
+            .current_dir(dir)
+            .exec_with(timeout)
+            .await?;
+        objects.paths.push(object);
+    }
+
+    Ok(objects)
+}
+
+async fn remove_if_exists(path: &Path) -> TsdlResult<()> {
+    match fs::remove_file(path).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(TsdlError::context(
+            format!("Removing {}", path.display()),
+            e,
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// `parser.c` and the external scanner, if any.
+async fn sources(src: &Path) -> Vec<PathBuf> {
+    let mut res = Vec::new();
+    for name in ["parser.c", "scanner.c", "scanner.cc", "scanner.cpp"] {
+        let path = src.join(name);
+        if fs::try_exists(&path).await.unwrap_or(false) {
+            res.push(path);
+        }
+    }
+    res
+}
+
+fn is_cxx(source: &Path) -> bool {
+    matches!(
+        source.extension().and_then(|ext| ext.to_str()),
+        Some("cc" | "cpp" | "cxx")
+    )
+}
+
+/// The C++ driver that goes with a C compiler.
+fn cxx_for(cc: &str) -> String {
+    if let Some(prefix) = cc.strip_suffix("gcc") {
+        format!("{prefix}g++")
+    } else if cc.ends_with("clang") {
+        format!("{cc}++")
+    } else if cc.contains("zig cc") {
+        cc.replacen("zig cc", "zig c++", 1)
+    } else if let Some(prefix) = cc.strip_suffix("cc") {
+        format!("{prefix}c++")
+    } else {
+        cc.to_string()
+    }
+}
+
+fn default_ext(triple: &str) -> &'static str {
+    if triple.contains("darwin") || triple.contains("apple") {
+        "dylib"
+    } else if triple.contains("windows") {
+        "dll"
+    } else {
+        "so"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cxx_for() {
+        assert_eq!(cxx_for("aarch64-linux-gnu-gcc"), "aarch64-linux-gnu-g++");
+        assert_eq!(cxx_for("clang"), "clang++");
+        assert_eq!(
+            cxx_for("zig cc -target aarch64-linux-gnu"),
+            "zig c++ -target aarch64-linux-gnu"
+        );
+        assert_eq!(cxx_for("cc"), "c++");
+    }
+
+    #[test]
+    fn test_debug_file() {
+        assert_eq!(
+            debug_file(Path::new("/out/libtree-sitter-json.so")),
+            PathBuf::from("/out/libtree-sitter-json.so.debug")
+        );
+    }
+
+    #[test]
+    fn test_resolve_defaults() {
+        let toolchain = Toolchain::resolve("aarch64-linux-gnu", None);
+        assert_eq!(toolchain.cc, "aarch64-linux-gnu-gcc");
+        assert_eq!(toolchain.cxx, "aarch64-linux-gnu-g++");
+        assert_eq!(toolchain.ext, "so");
+        assert_eq!(toolchain.sysroot, None);
+    }
+
+    #[test]
+    fn test_resolve_config() {
+        let config = ToolchainConfig {
+            cc: Some("zig cc -target aarch64-macos".to_string()),
+            cxx: None,
+            sysroot: Some(PathBuf::from("/opt/sysroot")),
+            ext: None,
+        };
+        let toolchain = Toolchain::resolve("aarch64-apple-darwin", Some(&config));
+        assert_eq!(toolchain.program(), "zig");
+        assert_eq!(toolchain.cxx, "zig c++ -target aarch64-macos");
+        assert_eq!(toolchain.ext, "dylib");
+    }
+}
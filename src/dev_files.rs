@@ -0,0 +1,178 @@
+//! What C and C++ projects need to use an installed parser: a pkg-config
+//! file, a `.cmake` package config, and a header declaring the language.
+
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+/// The installed artifacts a grammar's dev files point to.
+#[derive(Clone, Debug)]
+pub struct Artifacts<'a> {
+    pub grammar: &'a str,
+    pub version: &'a str,
+    pub include_dir: &'a Path,
+    pub shared: Option<&'a Path>,
+    pub soname: Option<&'a str>,
+    pub static_lib: Option<&'a Path>,
+}
+
+/// `tree-sitter-<grammar>`, the name of the package.
+#[must_use]
+pub fn package(grammar: &str) -> String {
+    format!("tree-sitter-{}", grammar.replace('_', "-"))
+}
+
+/// The version of a checkout without one: `0.0.0+g<commit>`, the way
+/// `git describe` abbreviates it, so it still sorts below any release.
+#[must_use]
+pub fn untagged_version(commit: Option<&str>) -> String {
+    match commit {
+        Some(commit) => format!("0.0.0+g{}", &commit[..commit.len().min(7)]),
+        None => String::from("0.0.0"),
+    }
+}
+
+/// `tree_sitter_<grammar>`, the function returning the language.
+#[must_use]
+pub fn symbol(grammar: &str) -> String {
+    format!("tree_sitter_{}", grammar.replace('-', "_"))
+}
+
+/// Where, in `out_dir`, the dev files of `grammar` go: the pkg-config
+/// file, the `.cmake` package config and the header.
+#[must_use]
+pub fn paths(out_dir: &Path, grammar: &str) -> [PathBuf; 3] {
+    let package = package(grammar);
+    [
+        out_dir.join("pkgconfig").join(format!("{package}.pc")),
+        out_dir
+            .join("cmake")
+            .join(&package)
+            .join(format!("{package}Config.cmake")),
+        out_dir
+            .join("include")
+            .join("tree_sitter")
+            .join(format!("{package}.h")),
+    ]
+}
+
+#[must_use]
+pub fn pkg_config(artifacts: &Artifacts) -> String {
+    let package = package(artifacts.grammar);
+    let lib = artifacts.shared.or(artifacts.static_lib);
+    let mut pc = format!(
+        "includedir={}\n\nName: {package}\nDescription: {} grammar for tree-sitter\nVersion: {}\nCflags: -I${{includedir}}\n",
+        artifacts.include_dir.display(),
+        artifacts.grammar,
+        artifacts.version,
+    );
+    if let Some(lib) = lib {
+        _ = writeln!(pc, "Libs: {}", lib.display());
+    }
+    pc
+}
+
+/// Imported targets `tree-sitter::<grammar>` for the shared library and
+/// `tree-sitter::<grammar>-static` for the static one.
+#[must_use]
+pub fn cmake_config(artifacts: &Artifacts) -> String {
+    let grammar = artifacts.grammar;
+    let include_dir = artifacts.include_dir.display();
+    let mut cmake = format!(
+        "# Generated by tsdl.\nset({package}_VERSION \"{version}\")\n",
+        package = package(grammar),
+        version = artifacts.version,
+    );
+
+    if let Some(shared) = artifacts.shared {
+        _ = write!(
+            cmake,
+            "\nif(NOT TARGET tree-sitter::{grammar})\n  add_library(tree-sitter::{grammar} SHARED IMPORTED)\n  set_target_properties(tree-sitter::{grammar} PROPERTIES\n    IMPORTED_LOCATION \"{}\"\n",
+            shared.display()
+        );
+        if let Some(soname) = artifacts.soname {
+            _ = writeln!(cmake, "    IMPORTED_SONAME \"{soname}\"");
+        }
+        _ = writeln!(
+            cmake,
+            "    INTERFACE_INCLUDE_DIRECTORIES \"{include_dir}\")\nendif()"
+        );
+    }
+
+    if let Some(static_lib) = artifacts.static_lib {
+        _ = write!(
+            cmake,
+            "\nif(NOT TARGET tree-sitter::{grammar}-static)\n  add_library(tree-sitter::{grammar}-static STATIC IMPORTED)\n  set_target_properties(tree-sitter::{grammar}-static PROPERTIES\n    IMPORTED_LOCATION \"{}\"\n    INTERFACE_INCLUDE_DIRECTORIES \"{include_dir}\")\nendif()\n",
+            static_lib.display()
+        );
+    }
+
+    cmake
+}
+
+#[must_use]
+pub fn header(grammar: &str) -> String {
+    let guard = format!("{}_H_", symbol(grammar).to_uppercase());
+    format!(
+        "#ifndef {guard}\n#define {guard}\n\ntypedef struct TSLanguage TSLanguage;\n\n#ifdef __cplusplus\nextern \"C\" {{\n#endif\n\nconst TSLanguage *{}(void);\n\n#ifdef __cplusplus\n}}\n#endif\n\n#endif // {guard}\n",
+        symbol(grammar)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn artifacts<'a>(shared: Option<&'a Path>, static_lib: Option<&'a Path>) -> Artifacts<'a> {
+        Artifacts {
+            grammar: "c_sharp",
+            version: "0.23.1",
+            include_dir: Path::new("/opt/parsers/include"),
+            shared,
+            soname: None,
+            static_lib,
+        }
+    }
+
+    #[test]
+    fn test_pkg_config() {
+        let shared = Path::new("/opt/parsers/libtree-sitter-c_sharp.so");
+        let pc = pkg_config(&artifacts(Some(shared), None));
+        assert!(pc.contains("Name: tree-sitter-c-sharp\n"));
+        assert!(pc.contains("Version: 0.23.1\n"));
+        assert!(pc.contains("includedir=/opt/parsers/include\n"));
+        assert!(pc.contains("Libs: /opt/parsers/libtree-sitter-c_sharp.so\n"));
+
+        let static_lib = Path::new("/opt/parsers/libtree-sitter-c_sharp.a");
+        let pc = pkg_config(&artifacts(None, Some(static_lib)));
+        assert!(pc.contains("Libs: /opt/parsers/libtree-sitter-c_sharp.a\n"));
+    }
+
+    #[test]
+    fn test_cmake_config() {
+        let cmake = cmake_config(&artifacts(
+            Some(Path::new("/opt/parsers/libtree-sitter-c_sharp.so")),
+            Some(Path::new("/opt/parsers/libtree-sitter-c_sharp.a")),
+        ));
+        assert!(cmake.contains("add_library(tree-sitter::c_sharp SHARED IMPORTED)"));
+        assert!(cmake.contains("add_library(tree-sitter::c_sharp-static STATIC IMPORTED)"));
+        assert!(!cmake.contains("IMPORTED_SONAME"));
+    }
+
+    #[test]
+    fn test_header() {
+        let header = header("c_sharp");
+        assert!(header.starts_with("#ifndef TREE_SITTER_C_SHARP_H_\n"));
+        assert!(header.contains("const TSLanguage *tree_sitter_c_sharp(void);"));
+    }
+
+    #[test]
+    fn test_untagged_version() {
+        assert_eq!(
+            untagged_version(Some("3f2a9c1d8e7b6a5f4e3d2c1b0a9f8e7d6c5b4a39")),
+            "0.0.0+g3f2a9c1"
+        );
+        assert_eq!(untagged_version(None), "0.0.0");
+    }
+}
@@ -104,8 +104,12 @@ pub struct LanguageCollection {
 
 impl fmt::Display for LanguageCollection {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        writeln!(f, "Could not figure out all languages:")?;
-        format_languages_inner(f, &self.related)
+        write!(f, "Could not figure out all languages:")?;
+        for lang in &self.related {
+            writeln!(f)?;
+            lang.format(f, 2)?;
+        }
+        Ok(())
     }
 }
 
@@ -271,16 +275,8 @@ pub enum ParserOp {
     Copy { src: PathBuf, dst: PathBuf },
     #[display("Could not generate in {}", dir.display())]
     Generate { dir: PathBuf },
-}
-
-fn format_languages_inner(w: &mut impl fmt::Write, langs: &[Language]) -> fmt::Result {
-    for (i, lang) in langs.iter().enumerate() {
-        if i > 0 {
-            write!(w, ", ")?;
-        }
-        write!(w, "{}", lang.name)?;
-    }
-    Ok(())
+    #[display("Hook {hook} failed in {}", dir.display())]
+    Hook { hook: &'static str, dir: PathBuf },
 }
 
 /// Main error type for tsdl operations
@@ -428,6 +424,12 @@ impl From<toml::de::Error> for TsdlError {
     }
 }
 
+impl From<serde_json::Error> for TsdlError {
+    fn from(e: serde_json::Error) -> Self {
+        TsdlError::Message(format!("JSON deserialization error: {e}"))
+    }
+}
+
 impl From<figment::Error> for TsdlError {
     fn from(e: figment::Error) -> Self {
         TsdlError::Message(format!("Configuration error: {e}"))
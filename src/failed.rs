@@ -0,0 +1,146 @@
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::{consts::TSDL_FAILED_FILE, error::TsdlError, TsdlResult};
+
+/// What failed in the last build, stored in `<build-dir>/<TSDL_FAILED_FILE>`.
+///
+/// Keys are `language/grammar` like in the cache, or just `language` when
+/// the language failed before its grammars were known, i.e. cloning.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Failed {
+    #[serde(default)]
+    pub keys: BTreeSet<String>,
+}
+
+impl Failed {
+    fn file(build_dir: &Path) -> PathBuf {
+        build_dir.join(TSDL_FAILED_FILE)
+    }
+
+    /// Load the failures of the last build, or none.
+    pub fn load(build_dir: &Path) -> TsdlResult<Self> {
+        let file = Self::file(build_dir);
+        if !file.exists() {
+            debug!("No failures file at {}", file.display());
+            return Ok(Self::default());
+        }
+
+        let contents = fs::read_to_string(&file).map_err(|e| {
+            TsdlError::context(format!("Reading failures file at {}", file.display()), e)
+        })?;
+
+        toml::from_str(&contents).map_err(|e| {
+            TsdlError::context(format!("Parsing failures file at {}", file.display()), e)
+        })
+    }
+
+    /// Write the failures to disk, or remove the file if there are none.
+    pub fn save(&self, build_dir: &Path) -> TsdlResult<()> {
+        let file = Self::file(build_dir);
+
+        if self.keys.is_empty() {
+            if file.exists() {
+                fs::remove_file(&file).map_err(|e| {
+                    TsdlError::context(format!("Deleting failures file at {}", file.display()), e)
+                })?;
+            }
+            return Ok(());
+        }
+
+        let contents = toml::to_string_pretty(self)
+            .map_err(|e| TsdlError::context("Serializing failures", e))?;
+        fs::write(&file, contents).map_err(|e| {
+            TsdlError::context(format!("Writing failures file at {}", file.display()), e)
+        })
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// The languages with failures.
+    #[must_use]
+    pub fn languages(&self) -> BTreeSet<String> {
+        self.keys
+            .iter()
+            .map(|key| language_of(key).to_string())
+            .collect()
+    }
+
+    /// The grammars of `language` to retry, or `None` for all of them.
+    #[must_use]
+    pub fn grammars(&self, language: &str) -> Option<BTreeSet<String>> {
+        let mut grammars = BTreeSet::new();
+        for key in &self.keys {
+            match key.split_once('/') {
+                Some((lang, grammar)) if lang == language => {
+                    grammars.insert(grammar.to_string());
+                }
+                None if key == language => return None,
+                _ => {}
+            }
+        }
+        Some(grammars)
+    }
+
+    /// Forget the failures of the languages that `ran` to the end, and
+    /// record the new ones. Languages that didn't run keep theirs.
+    pub fn update(
+        &mut self,
+        ran: impl Fn(&str) -> bool,
+        failures: impl IntoIterator<Item = String>,
+    ) {
+        self.keys.retain(|key| !ran(language_of(key)));
+        self.keys.extend(failures);
+    }
+}
+
+fn language_of(key: &str) -> &str {
+    key.split_once('/').map_or(key, |(language, _)| language)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn failed(keys: &[&str]) -> Failed {
+        Failed {
+            keys: keys.iter().map(ToString::to_string).collect(),
+        }
+    }
+
+    #[test]
+    fn test_grammars() {
+        let failed = failed(&["php", "typescript/tsx", "typescript/typescript"]);
+        assert_eq!(
+            failed.languages(),
+            BTreeSet::from(["php".to_string(), "typescript".to_string()])
+        );
+        assert_eq!(failed.grammars("php"), None);
+        assert_eq!(
+            failed.grammars("typescript"),
+            Some(BTreeSet::from([
+                "tsx".to_string(),
+                "typescript".to_string()
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_update() {
+        let mut failures = failed(&["php", "typescript/tsx", "java/java"]);
+        failures.update(
+            |language| language == "typescript" || language == "rust",
+            ["rust/rust".to_string()],
+        );
+        assert_eq!(failures, failed(&["java/java", "php", "rust/rust"]));
+    }
+}
@@ -4,6 +4,7 @@ use std::{
     io::Write,
     path::{Component, Path, PathBuf},
     process::{Output, Stdio},
+    time::Duration,
 };
 
 use serde::{Deserialize, Serialize};
@@ -99,6 +100,35 @@ async fn clean_anyway(cwd: &Path) -> TsdlResult<()> {
     Ok(())
 }
 
+/// Check `git_ref` out in `dir`, a new worktree of the repo in `cwd`, leaving
+/// the repo's own checkout alone.
+pub async fn add_worktree(cwd: &Path, dir: &Path, git_ref: &str) -> TsdlResult<()> {
+    Command::new("git")
+        .current_dir(cwd)
+        .args(["worktree", "add", "--quiet", "--detach"])
+        .arg(dir)
+        .arg(git_ref)
+        .exec()
+        .await?;
+    Ok(())
+}
+
+/// Remove the worktree in `dir`, if any, and forget the ones whose directory
+/// is gone.
+pub async fn remove_worktree(cwd: &Path, dir: &Path) -> TsdlResult<()> {
+    if dir.exists() {
+        tokio::fs::remove_dir_all(dir)
+            .await
+            .map_err(|e| TsdlError::context(format!("Removing {}", dir.display()), e))?;
+    }
+    Command::new("git")
+        .current_dir(cwd)
+        .args(["worktree", "prune"])
+        .exec()
+        .await?;
+    Ok(())
+}
+
 pub async fn clone(repo: &str, cwd: &Path) -> TsdlResult<()> {
     if cwd.exists() {
         Command::new("git")
@@ -115,8 +145,14 @@ pub async fn clone(repo: &str, cwd: &Path) -> TsdlResult<()> {
     Ok(())
 }
 
-pub async fn clone_fast(repo: &str, git_ref: &str, cwd: &Path) -> TsdlResult<()> {
-    clone_fast_with_force(repo, git_ref, cwd, false).await
+/// Fetch `git_ref` of `repo` into `cwd`; fetches are killed after `timeout`.
+pub async fn clone_fast(
+    repo: &str,
+    git_ref: &str,
+    cwd: &Path,
+    timeout: Option<Duration>,
+) -> TsdlResult<()> {
+    clone_fast_with_force(repo, git_ref, cwd, false, timeout).await
 }
 
 pub async fn clone_fast_with_force(
@@ -124,14 +160,15 @@ pub async fn clone_fast_with_force(
     git_ref: &str,
     cwd: &Path,
     force: bool,
+    timeout: Option<Duration>,
 ) -> TsdlResult<()> {
     if force || !is_same_remote(cwd, repo).await {
         clean_anyway(cwd).await?;
     }
     if is_valid_git_dir(cwd).await {
-        reset_head_hard(cwd, git_ref).await?;
+        reset_head_hard(cwd, git_ref, timeout).await?;
     } else {
-        init_fetch_and_checkout(cwd, repo, git_ref).await?;
+        init_fetch_and_checkout(cwd, repo, git_ref, timeout).await?;
     }
     Ok(())
 }
@@ -141,7 +178,7 @@ pub fn column(input: &str, indent: &str, width: usize) -> TsdlResult<Output> {
         .arg("column")
         .arg("--mode=always")
         .arg(format!("--indent={indent}"))
-        .arg(format!("--width={width}",))
+        .arg(format!("--width={width}"))
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .spawn()?;
@@ -161,12 +198,16 @@ pub fn column(input: &str, indent: &str, width: usize) -> TsdlResult<Output> {
         .map_err(|e| TsdlError::context("git column did not finish normally", e))
 }
 
-async fn fetch_and_checkout(cwd: &Path, git_ref: &str) -> TsdlResult<()> {
+async fn fetch_and_checkout(
+    cwd: &Path,
+    git_ref: &str,
+    timeout: Option<Duration>,
+) -> TsdlResult<()> {
     Command::new("git")
         .env("GIT_TERMINAL_PROMPT", "0")
         .current_dir(cwd)
         .args(["fetch", "origin", "--depth", "1", git_ref])
-        .exec()
+        .exec_with(timeout)
         .await?;
     Command::new("git")
         .current_dir(cwd)
@@ -176,7 +217,7 @@ async fn fetch_and_checkout(cwd: &Path, git_ref: &str) -> TsdlResult<()> {
     Ok(())
 }
 
-async fn get_head_sha1(cwd: &Path) -> TsdlResult<String> {
+pub async fn get_head_sha1(cwd: &Path) -> TsdlResult<String> {
     String::from_utf8(
         Command::new("git")
             .current_dir(cwd)
@@ -200,7 +241,12 @@ async fn get_remote_url(cwd: &Path) -> TsdlResult<String> {
     .map_err(|e| TsdlError::context("remote get-url origin did not return a valid utf-8", e))
 }
 
-async fn init_fetch_and_checkout(cwd: &Path, repo: &str, git_ref: &str) -> TsdlResult<()> {
+async fn init_fetch_and_checkout(
+    cwd: &Path,
+    repo: &str,
+    git_ref: &str,
+    timeout: Option<Duration>,
+) -> TsdlResult<()> {
     clean_anyway(cwd).await?;
     fs::create_dir_all(cwd).await?;
 
@@ -216,7 +262,7 @@ async fn init_fetch_and_checkout(cwd: &Path, repo: &str, git_ref: &str) -> TsdlR
         .exec()
         .await?;
 
-    fetch_and_checkout(cwd, git_ref).await?;
+    fetch_and_checkout(cwd, git_ref, timeout).await?;
 
     Ok(())
 }
@@ -242,67 +288,139 @@ async fn is_valid_git_dir(cwd: &Path) -> bool {
     is_inside_work_tree && can_parse_head
 }
 
-pub async fn list_grammar_files(cwd: &Path) -> TsdlResult<Vec<PathBuf>> {
-    let output = Command::new("git")
-        .current_dir(cwd)
-        .args(["ls-files", "--cached", "--others", "--exclude-standard"])
-        .exec()
-        .await?;
-
-    let stdout = String::from_utf8(output.stdout)
-        .map_err(|e| TsdlError::context("git ls-files output is not valid utf-8", e))?;
-
+/// Grammar files among `files`, skipping the usual non-grammar directories.
+#[must_use]
+pub fn grammar_files(files: &[PathBuf]) -> Vec<PathBuf> {
     let exclude = [
         ".github", "bindings", "doc", "docs", "examples", "queries", "script", "scripts", "test",
         "tests",
     ];
 
-    let result: Vec<PathBuf> = stdout
-        .lines()
-        .filter_map(|line| {
-            if line.is_empty() {
-                return None;
-            }
-
-            let path = Path::new(line);
-
+    files
+        .iter()
+        .filter(|path| {
             // Check if filename is exactly "grammar.js"
             if path.file_name() != Some(OsStr::new("grammar.js")) {
-                return None;
+                return false;
             }
 
             // Check if any path component is in excluded dirs
-            let has_excluded = path.components().any(|comp| {
+            !path.components().any(|comp| {
                 if let Component::Normal(name) = comp {
                     exclude.contains(&name.to_string_lossy().as_ref())
                 } else {
                     false
                 }
-            });
+            })
+        })
+        .cloned()
+        .collect()
+}
 
-            if has_excluded {
-                return None;
-            }
+/// Tracked and untracked (but not ignored) files, relative to `cwd`.
+pub async fn list_files(cwd: &Path) -> TsdlResult<Vec<PathBuf>> {
+    let output = Command::new("git")
+        .current_dir(cwd)
+        .args(["ls-files", "--cached", "--others", "--exclude-standard"])
+        .exec()
+        .await?;
 
-            Some(PathBuf::from(line))
-        })
-        .collect();
+    let stdout = String::from_utf8(output.stdout)
+        .map_err(|e| TsdlError::context("git ls-files output is not valid utf-8", e))?;
 
-    Ok(result)
+    Ok(stdout
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(PathBuf::from)
+        .collect())
 }
 
-async fn reset_head_hard(cwd: &Path, git_ref: &str) -> TsdlResult<()> {
+/// Untracked (but not ignored) files, relative to `cwd`.
+pub async fn list_untracked(cwd: &Path) -> TsdlResult<Vec<PathBuf>> {
+    let output = Command::new("git")
+        .current_dir(cwd)
+        .args(["ls-files", "--others", "--exclude-standard"])
+        .exec()
+        .await?;
+
+    let stdout = String::from_utf8(output.stdout)
+        .map_err(|e| TsdlError::context("git ls-files output is not valid utf-8", e))?;
+
+    Ok(stdout
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(PathBuf::from)
+        .collect())
+}
+
+/// Whether `path`, relative to `cwd`, is committed.
+pub async fn is_tracked(cwd: &Path, path: &Path) -> bool {
+    Command::new("git")
+        .current_dir(cwd)
+        .args(["ls-files", "--error-unmatch", "--"])
+        .arg(path)
+        .exec()
+        .await
+        .is_ok()
+}
+
+async fn reset_head_hard(cwd: &Path, git_ref: &str, timeout: Option<Duration>) -> TsdlResult<()> {
     if git_ref != get_head_sha1(cwd).await?.trim() {
         Command::new("git")
             .current_dir(cwd)
             .args(["reset", "--hard", "HEAD"])
             .exec()
             .await?;
-        fetch_and_checkout(cwd, git_ref).await?;
+        fetch_and_checkout(cwd, git_ref, timeout).await?;
     }
     Ok(())
 }
 
+/// The tags on `git_ref` itself, highest version first.
+pub async fn tags_at(cwd: &Path, git_ref: &str) -> TsdlResult<Vec<String>> {
+    let output = Command::new("git")
+        .current_dir(cwd)
+        .args(["tag", "--sort=-version:refname", "--points-at", git_ref])
+        .exec()
+        .await?;
+
+    let stdout = String::from_utf8(output.stdout)
+        .map_err(|e| TsdlError::context("git tag output is not valid utf-8", e))?;
+
+    Ok(stdout
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// The tags of the `origin` remote on `commit`. Checkouts are fetched
+/// shallow and without tags, so a branch's tags are only known there.
+pub async fn remote_tags_at(cwd: &Path, commit: &str) -> TsdlResult<Vec<String>> {
+    let output = Command::new("git")
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .current_dir(cwd)
+        .args(["ls-remote", "--tags", "origin"])
+        .exec()
+        .await?;
+
+    let stdout = String::from_utf8(output.stdout)
+        .map_err(|e| TsdlError::context("git ls-remote output is not valid utf-8", e))?;
+
+    // Annotated tags are listed twice: as the tag, and peeled (`^{}`) as the
+    // commit it tags.
+    let mut tags = stdout
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .filter(|(sha1, _)| *sha1 == commit)
+        .filter_map(|(_, name)| name.strip_prefix("refs/tags/"))
+        .map(|name| name.trim_end_matches("^{}").to_string())
+        .collect::<Vec<_>>();
+    tags.sort();
+    tags.dedup();
+    Ok(tags)
+}
+
 pub async fn tag_for_ref(cwd: &Path, git_ref: &str) -> TsdlResult<String> {
     // Try to find a tag for this ref
     let tag = Command::new("git")
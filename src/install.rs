@@ -0,0 +1,803 @@
+//! Putting built files into `out-dir`, the way `install-mode` says.
+//!
+//! Files are staged in `out-dir/.tsdl-staging/<language>/` and renamed into
+//! place when a [`Transaction`] commits, so `out-dir` never holds a
+//! half-written parser. What they replace is kept in `out-dir/.tsdl-previous/`
+//! for [`rollback`].
+
+use std::{
+    collections::HashMap,
+    fs as std_fs,
+    io::{self, Write},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use sha1::Sha1;
+use tokio::fs;
+use tracing::debug;
+
+use crate::{
+    app::App,
+    args::{InstallCommit, InstallMode},
+    build,
+    cache::hash_file,
+    consts::TSDL_MANIFEST_FILE,
+    error::TsdlError,
+    manifest::{self, Manifest},
+    TsdlResult,
+};
+
+pub const STAGING_DIR: &str = ".tsdl-staging";
+pub const PREVIOUS_DIR: &str = ".tsdl-previous";
+/// Lists, in [`PREVIOUS_DIR`], the files the last commit created.
+const ADDED_FILE: &str = ".tsdl-added";
+/// Where the manifest is staged, like a language of its own.
+const MANIFEST_STAGING: &str = ".tsdl-manifest";
+
+/// Where `language` stages what it installs into `out_dir`.
+#[must_use]
+pub fn staging_dir(out_dir: &Path, language: &str) -> PathBuf {
+    out_dir.join(STAGING_DIR).join(language)
+}
+
+/// What [`install`] had to do.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Installed {
+    /// `dst` was already installed from `src`.
+    Unchanged,
+    /// `dst` didn't exist.
+    New,
+    /// `dst` was replaced.
+    Replaced,
+}
+
+/// Stage `src` at `staged`, to be installed to `dst` on commit. Nothing is
+/// staged when `dst` is already installed from `src`, and an existing `dst`
+/// with other contents is only replaced with `force`.
+pub async fn stage(
+    mode: InstallMode,
+    src: &Path,
+    dst: &Path,
+    staged: &Path,
+    force: bool,
+) -> TsdlResult<Installed> {
+    let installed = match fs::symlink_metadata(dst).await {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Installed::New,
+        Err(e) => return Err(TsdlError::context(format!("Reading {}", dst.display()), e)),
+        Ok(_) => {
+            if is_installed(mode, src, dst).await? {
+                return Ok(Installed::Unchanged);
+            }
+            if !force && !same_contents(src, dst).await? {
+                return Err(TsdlError::message(format!(
+                    "Binary differs at {}. Use --force to overwrite",
+                    dst.display()
+                )));
+            }
+            Installed::Replaced
+        }
+    };
+
+    if let Some(dir) = staged.parent() {
+        fs::create_dir_all(dir)
+            .await
+            .map_err(|e| TsdlError::context(format!("Creating {}", dir.display()), e))?;
+    }
+    match fs::remove_file(staged).await {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+            return Err(TsdlError::context(
+                format!("Removing {}", staged.display()),
+                e,
+            ))
+        }
+        _ => {}
+    }
+
+    place(mode, src, staged).await.map_err(|e| {
+        TsdlError::context(
+            format!(
+                "Installing {} -> {} ({mode:?})",
+                src.display(),
+                dst.display()
+            ),
+            e,
+        )
+    })?;
+
+    Ok(installed)
+}
+
+/// Stage a symlink to `target` at `staged`, to be installed to `dst` on
+/// commit, unless `dst` already is one.
+pub async fn stage_symlink(target: &Path, dst: &Path, staged: &Path) -> TsdlResult<Installed> {
+    let installed = match fs::read_link(dst).await {
+        Ok(current) if current == target => return Ok(Installed::Unchanged),
+        Ok(_) => Installed::Replaced,
+        Err(_) if fs::symlink_metadata(dst).await.is_ok() => Installed::Replaced,
+        Err(_) => Installed::New,
+    };
+
+    if let Some(dir) = staged.parent() {
+        fs::create_dir_all(dir)
+            .await
+            .map_err(|e| TsdlError::context(format!("Creating {}", dir.display()), e))?;
+    }
+    _ = fs::remove_file(staged).await;
+    fs::symlink(target, staged).await.map_err(|e| {
+        TsdlError::context(
+            format!("Linking {} -> {}", dst.display(), target.display()),
+            e,
+        )
+    })?;
+
+    Ok(installed)
+}
+
+/// Stage a file tsdl generates with `contents` at `staged`, to be installed
+/// to `dst` on commit, unless `dst` already has them. Being tsdl's own, it
+/// is replaced without `--force`.
+pub async fn stage_contents(contents: &str, dst: &Path, staged: &Path) -> TsdlResult<Installed> {
+    let installed = match fs::read(dst).await {
+        Ok(current) if current == contents.as_bytes() => return Ok(Installed::Unchanged),
+        Ok(_) => Installed::Replaced,
+        Err(_) => Installed::New,
+    };
+
+    if let Some(dir) = staged.parent() {
+        fs::create_dir_all(dir)
+            .await
+            .map_err(|e| TsdlError::context(format!("Creating {}", dir.display()), e))?;
+    }
+    fs::write(staged, contents)
+        .await
+        .map_err(|e| TsdlError::context(format!("Writing {}", staged.display()), e))?;
+
+    Ok(installed)
+}
+
+/// Whether `dst` is already what `mode` would make of `src`.
+pub async fn is_installed(mode: InstallMode, src: &Path, dst: &Path) -> TsdlResult<bool> {
+    let is_link_to_src = fs::read_link(dst)
+        .await
+        .is_ok_and(|target| target == absolute(src));
+
+    match mode {
+        InstallMode::Symlink => Ok(is_link_to_src),
+        _ if is_link_to_src => Ok(false),
+        InstallMode::Hardlink => same_inode(src, dst).await,
+        InstallMode::Auto => Ok(same_inode(src, dst).await? || same_contents(src, dst).await?),
+        InstallMode::Copy | InstallMode::Reflink => {
+            Ok(!same_inode(src, dst).await? && same_contents(src, dst).await?)
+        }
+    }
+}
+
+/// What `mode` made of `src` at `dst`: `auto` is a hardlink, or a copy
+/// across filesystems.
+pub async fn resolved_mode(mode: InstallMode, src: &Path, dst: &Path) -> InstallMode {
+    match mode {
+        InstallMode::Auto if same_inode(src, dst).await.unwrap_or(false) => InstallMode::Hardlink,
+        InstallMode::Auto => InstallMode::Copy,
+        mode => mode,
+    }
+}
+
+async fn same_inode(src: &Path, dst: &Path) -> TsdlResult<bool> {
+    let src = metadata(src).await?;
+    let dst = metadata(dst).await?;
+    Ok(src.dev() == dst.dev() && src.ino() == dst.ino())
+}
+
+async fn same_contents(src: &Path, dst: &Path) -> TsdlResult<bool> {
+    if metadata(src).await?.len() != metadata(dst).await?.len() {
+        return Ok(false);
+    }
+    Ok(hash_file::<Sha1>(src).await? == hash_file::<Sha1>(dst).await?)
+}
+
+async fn metadata(path: &Path) -> TsdlResult<std::fs::Metadata> {
+    fs::metadata(path)
+        .await
+        .map_err(|e| TsdlError::context(format!("Reading {}", path.display()), e))
+}
+
+async fn place(mode: InstallMode, src: &Path, dst: &Path) -> io::Result<()> {
+    match mode {
+        InstallMode::Hardlink => fs::hard_link(src, dst).await,
+        InstallMode::Copy => fs::copy(src, dst).await.map(|_| ()),
+        InstallMode::Symlink => fs::symlink(absolute(src), dst).await,
+        InstallMode::Reflink => {
+            let (src, dst) = (src.to_path_buf(), dst.to_path_buf());
+            tokio::task::spawn_blocking(move || reflink(&src, &dst))
+                .await
+                .map_err(io::Error::other)?
+        }
+        InstallMode::Auto => match fs::hard_link(src, dst).await {
+            Err(e) if e.raw_os_error() == Some(libc::EXDEV) => fs::copy(src, dst).await.map(|_| ()),
+            res => res,
+        },
+    }
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(target_os = "linux")]
+fn reflink(src: &Path, dst: &Path) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let src_file = std::fs::File::open(src)?;
+    let dst_file = std::fs::File::create_new(dst)?;
+    // SAFETY: both descriptors are open for the duration of the call.
+    let res = unsafe { libc::ioctl(dst_file.as_raw_fd(), libc::FICLONE, src_file.as_raw_fd()) };
+    if res == 0 {
+        return Ok(());
+    }
+
+    let err = io::Error::last_os_error();
+    drop(dst_file);
+    _ = std::fs::remove_file(dst);
+    Err(err)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reflink(_src: &Path, _dst: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "reflink is only supported on Linux",
+    ))
+}
+
+/// The staged installs of a build, committed per run or per language.
+#[derive(Debug)]
+pub struct Transaction {
+    commit: InstallCommit,
+    out_dir: PathBuf,
+    /// Whether this run already replaced the previous generation.
+    started: bool,
+    /// What the manifest will say about grammars once their language commits.
+    pending: HashMap<String, Vec<manifest::Grammar>>,
+    committed: Vec<manifest::Grammar>,
+}
+
+impl Transaction {
+    /// Start over: whatever an interrupted run left staged is dropped.
+    pub fn new(out_dir: &Path, commit: InstallCommit) -> TsdlResult<Self> {
+        remove_dir(&out_dir.join(STAGING_DIR))?;
+        Ok(Self {
+            commit,
+            out_dir: out_dir.to_path_buf(),
+            started: false,
+            pending: HashMap::new(),
+            committed: Vec::new(),
+        })
+    }
+
+    /// `grammar` is staged; the manifest lists it once its language commits.
+    pub fn record(&mut self, grammar: manifest::Grammar) {
+        self.pending
+            .entry(grammar.language.clone())
+            .or_default()
+            .push(grammar);
+    }
+
+    /// `language` is done and all its grammars succeeded.
+    pub fn language_done(&mut self, language: &str) -> TsdlResult<()> {
+        if self.commit == InstallCommit::Language {
+            self.commit(language)?;
+        }
+        Ok(())
+    }
+
+    /// The run is over: commit what's left if it all succeeded, or drop it,
+    /// then update the manifest with what was committed.
+    pub fn finish(mut self, success: bool) -> TsdlResult<()> {
+        let staging = self.out_dir.join(STAGING_DIR);
+        if success && self.commit == InstallCommit::Run {
+            let mut languages = self.pending.keys().cloned().collect::<Vec<_>>();
+            for language in read_dir(&staging)? {
+                if language.is_dir() {
+                    let name = language.file_name().unwrap_or_default().to_string_lossy();
+                    languages.push(name.to_string());
+                }
+            }
+            languages.sort();
+            languages.dedup();
+            for language in languages {
+                self.commit(&language)?;
+            }
+        }
+        let manifest = self.stage_manifest();
+        let manifest = manifest.and_then(|()| self.commit(MANIFEST_STAGING));
+        remove_dir(&staging).and(manifest)
+    }
+
+    /// Stage the manifest, with what was committed, if that changes it.
+    fn stage_manifest(&mut self) -> TsdlResult<()> {
+        if self.committed.is_empty() {
+            return Ok(());
+        }
+
+        let mut manifest = Manifest::load(&self.out_dir)?;
+        let current = manifest.clone();
+        manifest.update(self.committed.drain(..));
+        if manifest == current {
+            return Ok(());
+        }
+
+        let staging = staging_dir(&self.out_dir, MANIFEST_STAGING);
+        create_dir(&staging)?;
+        let staged = staging.join(TSDL_MANIFEST_FILE);
+        std_fs::write(&staged, manifest.to_json()?)
+            .map_err(|e| TsdlError::context(format!("Writing {}", staged.display()), e))
+    }
+
+    /// Move `language`'s staged files into place, and remove the ones its
+    /// grammars don't install anymore, keeping what they replace or remove
+    /// for [`rollback`].
+    fn commit(&mut self, language: &str) -> TsdlResult<()> {
+        let grammars = self.pending.remove(language).unwrap_or_default();
+        let stale = self.stale(&grammars)?;
+        self.committed.extend(grammars);
+
+        let staging = staging_dir(&self.out_dir, language);
+        let files = files(&staging)?;
+        if files.is_empty() && stale.is_empty() {
+            return remove_dir(&staging);
+        }
+
+        let previous = self.out_dir.join(PREVIOUS_DIR);
+        if !self.started {
+            remove_dir(&previous)?;
+            create_dir(&previous)?;
+            self.started = true;
+        }
+
+        for rel in &stale {
+            let dst = self.out_dir.join(rel);
+            keep(&dst, &previous.join(rel))?;
+            std_fs::remove_file(&dst)
+                .map_err(|e| TsdlError::context(format!("Removing {}", dst.display()), e))?;
+        }
+
+        let mut added = Vec::new();
+        for rel in files {
+            let dst = self.out_dir.join(&rel);
+            if std_fs::symlink_metadata(&dst).is_ok() {
+                keep(&dst, &previous.join(&rel))?;
+            } else {
+                added.push(rel.clone());
+            }
+
+            if let Some(dir) = dst.parent() {
+                create_dir(dir)?;
+            }
+            rename(&staging.join(&rel), &dst)?;
+        }
+
+        if !added.is_empty() {
+            let list = previous.join(ADDED_FILE);
+            let mut file = std_fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&list)
+                .map_err(|e| TsdlError::context(format!("Opening {}", list.display()), e))?;
+            for rel in added {
+                writeln!(file, "{}", rel.display())
+                    .map_err(|e| TsdlError::context(format!("Writing {}", list.display()), e))?;
+            }
+        }
+
+        debug!("Committed {language} into {}", self.out_dir.display());
+        remove_dir(&staging)
+    }
+
+    /// The files `grammars` installed last time and don't anymore, like the
+    /// real file and SONAME link of a version they were since bumped from.
+    /// Files another grammar lists are left alone.
+    fn stale(&self, grammars: &[manifest::Grammar]) -> TsdlResult<Vec<PathBuf>> {
+        let manifest = Manifest::load(&self.out_dir)?;
+        let keys = grammars
+            .iter()
+            .map(|grammar| format!("{}/{}", grammar.language, grammar.grammar))
+            .collect::<Vec<_>>();
+        let listed = manifest
+            .grammars
+            .iter()
+            .filter(|(key, _)| !keys.contains(key))
+            .map(|(_, grammar)| grammar)
+            .chain(grammars)
+            .chain(&self.committed)
+            .chain(self.pending.values().flatten())
+            .flat_map(|grammar| &grammar.files)
+            .collect::<Vec<_>>();
+
+        Ok(keys
+            .iter()
+            .filter_map(|key| manifest.grammars.get(key))
+            .flat_map(|old| &old.files)
+            .filter(|file| !listed.contains(file))
+            // Directories, like queries, are only ever replaced.
+            .filter(|file| {
+                std_fs::symlink_metadata(self.out_dir.join(file)).is_ok_and(|m| !m.is_dir())
+            })
+            .cloned()
+            .collect())
+    }
+}
+
+/// Keep `dst` at `kept`, in the previous generation.
+fn keep(dst: &Path, kept: &Path) -> TsdlResult<()> {
+    if let Some(dir) = kept.parent() {
+        create_dir(dir)?;
+    }
+    _ = std_fs::remove_file(kept);
+    // A link keeps `dst` in place until the rename replaces it.
+    std_fs::hard_link(dst, kept)
+        .or_else(|_| std_fs::copy(dst, kept).map(|_| ()))
+        .map_err(|e| TsdlError::context(format!("Keeping {}", dst.display()), e))
+}
+
+/// Restore the files the last commit replaced, and remove the ones it added.
+pub fn rollback(app: &App) -> TsdlResult<()> {
+    let _guard = build::lock(app)?;
+    let restored = restore(&app.command.out_dir)?;
+    println!(
+        "Restored the previous parsers in {} ({restored} files)",
+        app.command.out_dir.display()
+    );
+    Ok(())
+}
+
+fn restore(out_dir: &Path) -> TsdlResult<usize> {
+    let previous = out_dir.join(PREVIOUS_DIR);
+    if !previous.is_dir() {
+        return Err(TsdlError::message(format!(
+            "Nothing to roll back in {}",
+            out_dir.display()
+        )));
+    }
+
+    let added = previous.join(ADDED_FILE);
+    let mut restored = 0;
+    for rel in files(&previous)? {
+        if rel.as_os_str() == ADDED_FILE {
+            continue;
+        }
+        let dst = out_dir.join(&rel);
+        if let Some(dir) = dst.parent() {
+            create_dir(dir)?;
+        }
+        rename(&previous.join(&rel), &dst)?;
+        restored += 1;
+    }
+
+    if let Ok(list) = std_fs::read_to_string(&added) {
+        for rel in list.lines().filter(|line| !line.is_empty()) {
+            let dst = out_dir.join(rel);
+            match std_fs::remove_file(&dst) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                    return Err(TsdlError::context(format!("Removing {}", dst.display()), e))
+                }
+                _ => restored += 1,
+            }
+        }
+    }
+
+    remove_dir(&previous)?;
+    Ok(restored)
+}
+
+/// Regular files and symlinks under `dir`, relative to it.
+pub fn files(dir: &Path) -> TsdlResult<Vec<PathBuf>> {
+    let mut res = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(current) = dirs.pop() {
+        for path in read_dir(&current)? {
+            let is_dir = std_fs::symlink_metadata(&path).is_ok_and(|meta| meta.is_dir());
+            if is_dir {
+                dirs.push(path);
+            } else if let Ok(rel) = path.strip_prefix(dir) {
+                res.push(rel.to_path_buf());
+            }
+        }
+    }
+    res.sort();
+    Ok(res)
+}
+
+fn read_dir(dir: &Path) -> TsdlResult<Vec<PathBuf>> {
+    match std_fs::read_dir(dir) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(TsdlError::context(format!("Reading {}", dir.display()), e)),
+        Ok(entries) => Ok(entries.filter_map(Result::ok).map(|e| e.path()).collect()),
+    }
+}
+
+fn create_dir(dir: &Path) -> TsdlResult<()> {
+    std_fs::create_dir_all(dir)
+        .map_err(|e| TsdlError::context(format!("Creating {}", dir.display()), e))
+}
+
+fn remove_dir(dir: &Path) -> TsdlResult<()> {
+    match std_fs::remove_dir_all(dir) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+            Err(TsdlError::context(format!("Removing {}", dir.display()), e))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn rename(src: &Path, dst: &Path) -> TsdlResult<()> {
+    std_fs::rename(src, dst).map_err(|e| {
+        TsdlError::context(format!("Moving {} -> {}", src.display(), dst.display()), e)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Setup {
+        _tmp: tempfile::TempDir,
+        src: PathBuf,
+        out_dir: PathBuf,
+        dst: PathBuf,
+        staged: PathBuf,
+    }
+
+    async fn setup() -> Setup {
+        let tmp = tempfile::tempdir().unwrap();
+        let src = tmp.path().join("build").join("libtree-sitter-json.so");
+        let out_dir = tmp.path().join("out");
+        fs::create_dir_all(src.parent().unwrap()).await.unwrap();
+        fs::create_dir_all(&out_dir).await.unwrap();
+        fs::write(&src, b"parser").await.unwrap();
+        Setup {
+            dst: out_dir.join("libtree-sitter-json.so"),
+            staged: staging_dir(&out_dir, "json").join("libtree-sitter-json.so"),
+            _tmp: tmp,
+            src,
+            out_dir,
+        }
+    }
+
+    impl Setup {
+        async fn install(&self, mode: InstallMode, force: bool) -> TsdlResult<Installed> {
+            let mut transaction = Transaction::new(&self.out_dir, InstallCommit::Run)?;
+            let installed = stage(mode, &self.src, &self.dst, &self.staged, force).await?;
+            transaction.language_done("json")?;
+            transaction.finish(true)?;
+            Ok(installed)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hardlink() {
+        let s = setup().await;
+        assert_eq!(
+            s.install(InstallMode::Hardlink, false).await.unwrap(),
+            Installed::New
+        );
+        assert!(same_inode(&s.src, &s.dst).await.unwrap());
+        assert_eq!(
+            s.install(InstallMode::Hardlink, false).await.unwrap(),
+            Installed::Unchanged
+        );
+    }
+
+    #[tokio::test]
+    async fn test_copy_then_symlink() {
+        let s = setup().await;
+        assert_eq!(
+            s.install(InstallMode::Copy, false).await.unwrap(),
+            Installed::New
+        );
+        assert!(!same_inode(&s.src, &s.dst).await.unwrap());
+        assert_eq!(
+            s.install(InstallMode::Copy, false).await.unwrap(),
+            Installed::Unchanged
+        );
+
+        // Same contents, so no --force needed to switch modes.
+        assert_eq!(
+            s.install(InstallMode::Symlink, false).await.unwrap(),
+            Installed::Replaced
+        );
+        assert_eq!(fs::read_link(&s.dst).await.unwrap(), s.src);
+    }
+
+    #[tokio::test]
+    async fn test_differs_needs_force() {
+        let s = setup().await;
+        fs::write(&s.dst, b"other!").await.unwrap();
+
+        let res = s.install(InstallMode::Auto, false).await;
+        assert!(res.is_err_and(|e| e.to_string().contains("Binary differs")));
+        assert_eq!(
+            s.install(InstallMode::Auto, true).await.unwrap(),
+            Installed::Replaced
+        );
+        assert_eq!(fs::read(&s.dst).await.unwrap(), b"parser");
+    }
+
+    #[tokio::test]
+    async fn test_failed_run_installs_nothing() {
+        let s = setup().await;
+        let transaction = Transaction::new(&s.out_dir, InstallCommit::Run).unwrap();
+        stage(InstallMode::Copy, &s.src, &s.dst, &s.staged, false)
+            .await
+            .unwrap();
+        transaction.finish(false).unwrap();
+
+        assert!(!s.dst.exists());
+        assert!(!s.out_dir.join(STAGING_DIR).exists());
+    }
+
+    #[tokio::test]
+    async fn test_rollback() {
+        let s = setup().await;
+        s.install(InstallMode::Copy, false).await.unwrap();
+        fs::write(&s.src, b"parser v2").await.unwrap();
+        s.install(InstallMode::Copy, true).await.unwrap();
+        assert_eq!(fs::read(&s.dst).await.unwrap(), b"parser v2");
+
+        assert_eq!(restore(&s.out_dir).unwrap(), 1);
+        assert_eq!(fs::read(&s.dst).await.unwrap(), b"parser");
+        assert!(restore(&s.out_dir).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_symlink_chain() {
+        let s = setup().await;
+        s.install(InstallMode::Copy, false).await.unwrap();
+
+        let mut transaction = Transaction::new(&s.out_dir, InstallCommit::Run).unwrap();
+        let staging = staging_dir(&s.out_dir, "json");
+        let real = s.out_dir.join("libtree-sitter-json.so.0.21.0");
+        let soname = s.out_dir.join("libtree-sitter-json.so.0");
+        stage(
+            InstallMode::Copy,
+            &s.src,
+            &real,
+            &staging.join("libtree-sitter-json.so.0.21.0"),
+            false,
+        )
+        .await
+        .unwrap();
+        let links = [
+            (&soname, "libtree-sitter-json.so.0.21.0"),
+            (&s.dst, "libtree-sitter-json.so.0"),
+        ];
+        for (dst, target) in links {
+            let staged = staging.join(dst.file_name().unwrap());
+            let installed = stage_symlink(Path::new(target), dst, &staged).await;
+            assert!(installed.is_ok());
+        }
+        transaction.language_done("json").unwrap();
+        transaction.finish(true).unwrap();
+
+        assert_eq!(fs::read(&s.dst).await.unwrap(), b"parser");
+        assert_eq!(
+            fs::read_link(&s.dst).await.unwrap(),
+            Path::new("libtree-sitter-json.so.0")
+        );
+        assert_eq!(
+            stage_symlink(
+                Path::new("libtree-sitter-json.so.0.21.0"),
+                &soname,
+                &staging.join("libtree-sitter-json.so.0")
+            )
+            .await
+            .unwrap(),
+            Installed::Unchanged
+        );
+
+        // Rolling back brings the unversioned library back.
+        restore(&s.out_dir).unwrap();
+        assert!(fs::read_link(&s.dst).await.is_err());
+        assert!(!real.exists());
+        assert!(fs::symlink_metadata(&soname).await.is_err());
+    }
+
+    fn grammar(language: &str) -> manifest::Grammar {
+        manifest::Grammar {
+            language: language.to_string(),
+            grammar: language.to_string(),
+            repo: format!("https://github.com/tree-sitter/tree-sitter-{language}"),
+            git_ref: "master".to_string(),
+            commit: None,
+            tree_sitter: "0.26.5".to_string(),
+            abi: Some(15),
+            scope: None,
+            file_types: Vec::new(),
+            license: None,
+            artifacts: std::collections::BTreeMap::new(),
+            queries: None,
+            files: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_manifest() {
+        let s = setup().await;
+
+        // Only languages that commit make it into the manifest.
+        let mut transaction = Transaction::new(&s.out_dir, InstallCommit::Language).unwrap();
+        transaction.record(grammar("json"));
+        transaction.record(grammar("yaml"));
+        transaction.language_done("json").unwrap();
+        transaction.finish(false).unwrap();
+        let manifest = Manifest::load(&s.out_dir).unwrap();
+        assert_eq!(manifest.grammars.keys().collect::<Vec<_>>(), ["json/json"]);
+
+        // Later runs keep what they didn't rebuild.
+        let mut transaction = Transaction::new(&s.out_dir, InstallCommit::Run).unwrap();
+        transaction.record(grammar("yaml"));
+        transaction.finish(true).unwrap();
+        let manifest = Manifest::load(&s.out_dir).unwrap();
+        assert_eq!(
+            manifest.grammars.keys().collect::<Vec<_>>(),
+            ["json/json", "yaml/yaml"]
+        );
+        assert!(!s.out_dir.join(STAGING_DIR).exists());
+    }
+
+    #[tokio::test]
+    async fn test_version_bump_removes_stale_chain() {
+        let s = setup().await;
+        let install = |version: &str, major: &str| {
+            let real = format!("libtree-sitter-json.so.{version}");
+            let soname = format!("libtree-sitter-json.so.{major}");
+            let staging = staging_dir(&s.out_dir, "json");
+            let (src, out_dir) = (s.src.clone(), s.out_dir.clone());
+            async move {
+                let mut transaction = Transaction::new(&out_dir, InstallCommit::Run).unwrap();
+                let dst = out_dir.join(&real);
+                stage(InstallMode::Copy, &src, &dst, &staging.join(&real), true)
+                    .await
+                    .unwrap();
+                for (link, target) in [
+                    (&soname, &real),
+                    (&"libtree-sitter-json.so".to_string(), &soname),
+                ] {
+                    stage_symlink(Path::new(target), &out_dir.join(link), &staging.join(link))
+                        .await
+                        .unwrap();
+                }
+                transaction.record(manifest::Grammar {
+                    files: vec![
+                        PathBuf::from("libtree-sitter-json.so"),
+                        PathBuf::from(&real),
+                        PathBuf::from(&soname),
+                    ],
+                    ..grammar("json")
+                });
+                transaction.finish(true).unwrap();
+            }
+        };
+
+        install("0.21.0", "0").await;
+        install("1.0.0", "1").await;
+        assert!(s.out_dir.join("libtree-sitter-json.so.1.0.0").exists());
+        assert!(
+            fs::symlink_metadata(s.out_dir.join("libtree-sitter-json.so.0"))
+                .await
+                .is_err()
+        );
+        assert!(!s.out_dir.join("libtree-sitter-json.so.0.21.0").exists());
+
+        // Rolling back brings the old chain back.
+        restore(&s.out_dir).unwrap();
+        assert!(s.out_dir.join("libtree-sitter-json.so.0.21.0").exists());
+        assert_eq!(
+            fs::read_link(s.out_dir.join("libtree-sitter-json.so.0"))
+                .await
+                .unwrap(),
+            Path::new("libtree-sitter-json.so.0.21.0")
+        );
+        assert!(!s.out_dir.join("libtree-sitter-json.so.1.0.0").exists());
+    }
+}
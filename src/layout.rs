@@ -0,0 +1,170 @@
+//! Where artifacts go in `out-dir`: `<prefix><grammar>.<ext>`, or wherever
+//! an `output-template` like `{target}/{language}/{prefix}{grammar}.{ext}`
+//! puts them.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Component, Path, PathBuf},
+    sync::Mutex,
+};
+
+use crate::{error::TsdlError, TsdlResult};
+
+/// The placeholders an `output-template` can use.
+pub const PLACEHOLDERS: [&str; 5] = ["target", "language", "prefix", "grammar", "ext"];
+
+/// What fills an `output-template`'s placeholders for one artifact.
+#[derive(Clone, Copy, Debug)]
+pub struct Names<'a> {
+    /// `native`, `wasm`, `static`, `object`, or the triple of a cross build.
+    pub target: &'a str,
+    pub language: &'a str,
+    pub prefix: &'a str,
+    pub grammar: &'a str,
+    pub ext: &'a str,
+}
+
+/// Check that `template`, the value of `option`, only uses known
+/// placeholders and stays inside `out-dir`.
+pub fn validate(option: &str, template: &str) -> TsdlResult<()> {
+    let invalid = |why: String| TsdlError::message(format!("Invalid {option} {template:?}: {why}"));
+
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        if rest[..start].contains('}') {
+            return Err(invalid("unopened }".to_string()));
+        }
+        let Some(len) = rest[start..].find('}') else {
+            return Err(invalid("unclosed {".to_string()));
+        };
+        let name = &rest[start + 1..start + len];
+        if !PLACEHOLDERS.contains(&name) {
+            return Err(invalid(format!(
+                "unknown placeholder {{{name}}}; use {}",
+                PLACEHOLDERS.map(|p| format!("{{{p}}}")).join(", ")
+            )));
+        }
+        rest = &rest[start + len + 1..];
+    }
+    if rest.contains('}') {
+        return Err(invalid("unopened }".to_string()));
+    }
+
+    let path = Path::new(template);
+    if path
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(invalid(
+            "it must be a relative path inside out-dir".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Fill in `template`, which [`validate`] accepted.
+#[must_use]
+pub fn render(template: &str, names: &Names) -> PathBuf {
+    let mut path = template.to_string();
+    for (placeholder, value) in [
+        ("{target}", names.target),
+        ("{language}", names.language),
+        ("{prefix}", names.prefix),
+        ("{grammar}", names.grammar),
+        ("{ext}", names.ext),
+    ] {
+        path = path.replace(placeholder, value);
+    }
+    PathBuf::from(path)
+}
+
+/// The files grammars will install, so that no two install the same one.
+#[derive(Debug, Default)]
+pub struct Claims {
+    owners: Mutex<HashMap<PathBuf, String>>,
+}
+
+impl Claims {
+    /// Claim `paths` for `owner` (a `language/grammar` key), or none of them
+    /// if another grammar already claimed one.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the claims lock is poisoned.
+    pub fn claim(&self, owner: &str, paths: &[PathBuf]) -> TsdlResult<()> {
+        let mut owners = self.owners.lock().expect("claims lock poisoned");
+
+        let mut seen = HashSet::new();
+        for path in paths {
+            let collision = match owners.get(path) {
+                _ if !seen.insert(path) => {
+                    format!("{owner} would install {} twice", path.display())
+                }
+                Some(other) if other != owner => {
+                    format!("{owner} and {other} would both install {}", path.display())
+                }
+                _ => continue,
+            };
+            return Err(TsdlError::message(format!(
+                "{collision}; make output-template tell them apart"
+            )));
+        }
+
+        for path in paths {
+            owners.insert(path.clone(), owner.to_string());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NAMES: Names = Names {
+        target: "native",
+        language: "typescript",
+        prefix: "libtree-sitter-",
+        grammar: "tsx",
+        ext: "so",
+    };
+
+    #[test]
+    fn test_render() {
+        let template = "{target}/{language}/{prefix}{grammar}.{ext}";
+        assert!(validate("output-template", template).is_ok());
+        assert_eq!(
+            render(template, &NAMES),
+            PathBuf::from("native/typescript/libtree-sitter-tsx.so")
+        );
+        assert_eq!(
+            render("parser/{grammar}.{ext}", &NAMES),
+            PathBuf::from("parser/tsx.so")
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate("output-template", "{name}.{ext}").is_err());
+        assert!(validate("output-template", "{grammar.{ext}").is_err());
+        assert!(validate("output-template", "grammar}.{ext}").is_err());
+        assert!(validate("output-template", "../{grammar}.{ext}").is_err());
+        assert!(validate("output-template", "/usr/lib/{grammar}.{ext}").is_err());
+    }
+
+    #[test]
+    fn test_claims() {
+        let claims = Claims::default();
+        let tsx = [PathBuf::from("/out/parser.so")];
+        assert!(claims.claim("typescript/tsx", &tsx).is_ok());
+        assert!(claims.claim("typescript/tsx", &tsx).is_ok());
+        let err = claims.claim("typescript/typescript", &tsx).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("typescript/typescript and typescript/tsx"));
+        assert!(claims
+            .claim("json/json", &[tsx[0].clone(), tsx[0].clone()])
+            .is_err());
+    }
+}
@@ -19,6 +19,8 @@
 //! json = "0.21.0" # The leading v is not necessary
 //! python = "master"
 //! typescript = { ref = "0.21.0", cmd = "make" }
+//! php = { ref = "v0.23.11", generate = "never" } # build from the committed src/parser.c
+//! rust = { ref = "v0.23.2", abi = 14, js-runtime = "bun" }
 //! cobol = { ref = "6a469068cacb5e3955bb16ad8dfff0dd792883c9", from = "https://github.com/yutaro-sakamoto/tree-sitter-cobol" }
 //! ```
 //!
@@ -65,17 +67,32 @@ pub mod app;
 pub mod args;
 pub mod build;
 pub mod cache;
+pub mod cancel;
+pub mod cc;
 pub mod config;
 pub mod consts;
+pub mod dev_files;
 pub mod display;
 pub mod error;
+pub mod failed;
 pub mod git;
+pub mod install;
+pub mod layout;
+pub mod license;
+pub mod limits;
 pub mod lock;
 pub mod logging;
+pub mod manifest;
+pub mod pack;
 pub mod parser;
+pub mod plan;
+pub mod sandbox;
 #[macro_use]
 pub mod sh;
+pub mod soname;
 pub mod tree_sitter;
+pub mod tree_sitter_json;
+pub mod verify;
 pub mod walk;
 
 pub trait SafeCanonicalize {
@@ -0,0 +1,198 @@
+//! The licenses grammars are distributed under: their `LICENSE*` and
+//! `COPYING*` files, installed in `out-dir/licenses/<language>/`, and an SPDX
+//! identifier for the manifest.
+
+use std::path::{Path, PathBuf};
+
+use tokio::fs;
+
+use crate::{tree_sitter_json::TreeSitterJson, TsdlResult};
+
+pub const LICENSES_DIR: &str = "licenses";
+
+/// What texts, from the most specific, tell which license a file holds.
+const TEXTS: [(&str, &[&str]); 13] = [
+    ("Apache-2.0", &["Apache License", "Version 2.0"]),
+    (
+        "LGPL-3.0",
+        &["GNU LESSER GENERAL PUBLIC LICENSE", "Version 3"],
+    ),
+    (
+        "LGPL-2.1",
+        &["GNU LESSER GENERAL PUBLIC LICENSE", "Version 2.1"],
+    ),
+    ("AGPL-3.0", &["GNU AFFERO GENERAL PUBLIC LICENSE"]),
+    ("GPL-3.0", &["GNU GENERAL PUBLIC LICENSE", "Version 3"]),
+    ("GPL-2.0", &["GNU GENERAL PUBLIC LICENSE", "Version 2"]),
+    ("MPL-2.0", &["Mozilla Public License", "2.0"]),
+    (
+        "BSD-3-Clause",
+        &[
+            "Redistribution and use in source and binary forms",
+            "Neither the name",
+        ],
+    ),
+    (
+        "BSD-2-Clause",
+        &["Redistribution and use in source and binary forms"],
+    ),
+    ("ISC", &["Permission to use, copy, modify, and"]),
+    ("MIT", &["Permission is hereby granted, free of charge"]),
+    (
+        "Unlicense",
+        &["This is free and unencumbered software released into the public domain"],
+    ),
+    ("CC0-1.0", &["CC0 1.0 Universal"]),
+];
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct License {
+    /// Like `MIT`, when the repo says or its license file tells.
+    pub spdx: Option<String>,
+    /// The license files at the root of the checkout.
+    pub files: Vec<PathBuf>,
+}
+
+impl License {
+    /// The license of the checkout in `repo`: its SPDX identifier comes from
+    /// `tree-sitter.json`, `package.json`, or else its license files.
+    pub async fn find(repo: &Path) -> TsdlResult<Self> {
+        let mut files = Vec::new();
+        if let Ok(mut entries) = fs::read_dir(repo).await {
+            while let Ok(Some(entry)) = entries.next_entry().await {
+                let is_file = entry.file_type().await.is_ok_and(|t| t.is_file());
+                if is_file && is_license_file(&entry.file_name().to_string_lossy()) {
+                    files.push(entry.path());
+                }
+            }
+        }
+        files.sort();
+
+        let mut spdx = TreeSitterJson::load(repo)
+            .await
+            .ok()
+            .flatten()
+            .and_then(|json| json.metadata.license);
+        if spdx.is_none() {
+            spdx = from_package_json(repo).await;
+        }
+        if spdx.is_none() {
+            for file in &files {
+                if let Ok(text) = fs::read_to_string(file).await {
+                    spdx = detect(&text).map(str::to_string);
+                }
+                if spdx.is_some() {
+                    break;
+                }
+            }
+        }
+
+        Ok(Self { spdx, files })
+    }
+
+    /// Where its files get installed, for `language`.
+    #[must_use]
+    pub fn installed_files(&self, out_dir: &Path, language: &str) -> Vec<PathBuf> {
+        let dir = out_dir.join(LICENSES_DIR).join(language);
+        self.files
+            .iter()
+            .filter_map(|file| file.file_name())
+            .map(|name| dir.join(name))
+            .collect()
+    }
+}
+
+/// `LICENSE`, `LICENSE.md`, `LICENSE-MIT`, `COPYING`, ...
+#[must_use]
+pub fn is_license_file(name: &str) -> bool {
+    let name = name.to_ascii_uppercase();
+    ["LICENSE", "LICENCE", "COPYING"]
+        .iter()
+        .any(|prefix| name.starts_with(prefix))
+}
+
+/// The SPDX identifier of a license's `text`, for the common ones.
+#[must_use]
+pub fn detect(text: &str) -> Option<&'static str> {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    TEXTS
+        .iter()
+        .find(|(_, needles)| needles.iter().all(|needle| text.contains(needle)))
+        .map(|(spdx, _)| *spdx)
+}
+
+/// The `license` of `package.json`, or its old `{ "type": ... }` form.
+async fn from_package_json(repo: &Path) -> Option<String> {
+    let content = fs::read_to_string(repo.join("package.json")).await.ok()?;
+    let package: serde_json::Value = serde_json::from_str(&content).ok()?;
+    let license = package.get("license")?;
+    license
+        .as_str()
+        .or_else(|| license.get("type")?.as_str())
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_license_file() {
+        for name in [
+            "LICENSE",
+            "LICENSE.md",
+            "license.txt",
+            "LICENSE-MIT",
+            "COPYING",
+        ] {
+            assert!(is_license_file(name), "{name}");
+        }
+        assert!(!is_license_file("README.md"));
+        assert!(!is_license_file("package.json"));
+    }
+
+    #[test]
+    fn test_detect() {
+        let mit = "MIT License\n\nCopyright (c) 2014 Max Brunsfeld\n\nPermission is hereby granted,\nfree of charge, to any person obtaining a copy";
+        assert_eq!(detect(mit), Some("MIT"));
+        let apache = "                                 Apache License\n                           Version 2.0, January 2004";
+        assert_eq!(detect(apache), Some("Apache-2.0"));
+        let bsd = "Redistribution and use in source and binary forms, with or without\nmodification, are permitted\n...\n3. Neither the name of the copyright holder";
+        assert_eq!(detect(bsd), Some("BSD-3-Clause"));
+        assert_eq!(detect("All rights reserved."), None);
+    }
+
+    #[tokio::test]
+    async fn test_find() {
+        let tmp = tempfile::tempdir().unwrap();
+        let repo = tmp.path();
+        fs::write(
+            repo.join("LICENSE"),
+            "Permission is hereby granted, free of charge",
+        )
+        .await
+        .unwrap();
+        fs::write(repo.join("grammar.js"), "").await.unwrap();
+
+        let license = License::find(repo).await.unwrap();
+        assert_eq!(license.files, [repo.join("LICENSE")]);
+        assert_eq!(license.spdx.as_deref(), Some("MIT"));
+        assert_eq!(
+            license.installed_files(Path::new("/out"), "json"),
+            [PathBuf::from("/out/licenses/json/LICENSE")]
+        );
+
+        // What the repo says wins over detection.
+        fs::write(repo.join("package.json"), r#"{ "license": "Apache-2.0" }"#)
+            .await
+            .unwrap();
+        let license = License::find(repo).await.unwrap();
+        assert_eq!(license.spdx.as_deref(), Some("Apache-2.0"));
+
+        let empty = tempfile::tempdir().unwrap();
+        assert_eq!(
+            License::find(empty.path()).await.unwrap(),
+            License::default()
+        );
+    }
+}
@@ -0,0 +1,167 @@
+//! How much runs at once: clones are network-bound and can go wide, while
+//! compiles are CPU- and memory-heavy. And how many failures a build takes
+//! before it stops.
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use sysinfo::System;
+
+use crate::args::BuildCommand;
+
+/// How often a compile waiting on memory checks again.
+const MEMORY_POLL: Duration = Duration::from_millis(500);
+
+#[derive(Debug)]
+pub struct Limits {
+    pub build_jobs: usize,
+    pub fetch_jobs: usize,
+    pub max_failures: Option<usize>,
+    pub memory: Option<MemoryGuard>,
+}
+
+impl Limits {
+    /// `--fetch-jobs` and `--build-jobs` default to `--jobs`, and
+    /// `--fail-fast` is `--max-failures 1`.
+    #[must_use]
+    pub fn new(command: &BuildCommand) -> Self {
+        Self {
+            build_jobs: command.build_jobs.unwrap_or(command.jobs).max(1),
+            fetch_jobs: command.fetch_jobs.unwrap_or(command.jobs).max(1),
+            max_failures: if command.fail_fast {
+                Some(1)
+            } else {
+                command.max_failures.map(|max| max.max(1))
+            },
+            memory: command.max_memory.map(MemoryGuard::new),
+        }
+    }
+}
+
+/// Holds new compiles back while the system uses more than `max` bytes of
+/// memory. One compile always gets to run, so a busy machine slows the build
+/// down instead of stalling it.
+#[derive(Debug)]
+pub struct MemoryGuard {
+    /// Held while a compile is checked and admitted, so two waiting compiles
+    /// can't both see room for one.
+    admission: tokio::sync::Mutex<()>,
+    max: u64,
+    running: AtomicUsize,
+    system: Arc<Mutex<System>>,
+}
+
+/// A compile admitted by [`MemoryGuard::acquire`]; ends when dropped.
+pub struct MemoryPermit<'a> {
+    guard: &'a MemoryGuard,
+}
+
+impl Drop for MemoryPermit<'_> {
+    fn drop(&mut self) {
+        self.guard.running.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl MemoryGuard {
+    #[must_use]
+    pub fn new(max_mb: u64) -> Self {
+        Self {
+            admission: tokio::sync::Mutex::new(()),
+            max: max_mb.saturating_mul(1024 * 1024),
+            running: AtomicUsize::new(0),
+            system: Arc::new(Mutex::new(System::new())),
+        }
+    }
+
+    /// Whether the system uses more memory than `max` right now, so a new
+    /// compile would have to wait. Reading the system's memory blocks, so
+    /// it's done off the executor.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the system info lock is poisoned.
+    pub async fn is_over_budget(&self) -> bool {
+        let system = self.system.clone();
+        let used = tokio::task::spawn_blocking(move || {
+            let mut system = system.lock().expect("system info lock poisoned");
+            system.refresh_memory();
+            system.used_memory()
+        })
+        .await
+        .expect("reading the system's memory panicked");
+        used > self.max
+    }
+
+    /// Wait until a compile can start.
+    pub async fn acquire(&self) -> MemoryPermit<'_> {
+        loop {
+            {
+                let _admission = self.admission.lock().await;
+                if self.running.load(Ordering::SeqCst) == 0 || !self.is_over_budget().await {
+                    self.running.fetch_add(1, Ordering::SeqCst);
+                    return MemoryPermit { guard: self };
+                }
+            }
+            tokio::time::sleep(MEMORY_POLL).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_jobs_default_to_jobs() {
+        let command = BuildCommand {
+            build_jobs: Some(2),
+            jobs: 8,
+            ..BuildCommand::default()
+        };
+        let limits = Limits::new(&command);
+        assert_eq!(limits.build_jobs, 2);
+        assert_eq!(limits.fetch_jobs, 8);
+        assert!(limits.memory.is_none());
+        assert_eq!(limits.max_failures, None);
+    }
+
+    #[test]
+    fn test_fail_fast() {
+        let command = BuildCommand {
+            fail_fast: true,
+            max_failures: Some(3),
+            ..BuildCommand::default()
+        };
+        assert_eq!(Limits::new(&command).max_failures, Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_memory_guard_lets_one_through() {
+        let guard = MemoryGuard::new(0);
+        assert!(guard.is_over_budget().await);
+
+        let permit = guard.acquire().await;
+        assert_eq!(guard.running.load(Ordering::SeqCst), 1);
+        drop(permit);
+        assert_eq!(guard.running.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_memory_guard_holds_the_next_back() {
+        let guard = MemoryGuard::new(0);
+        let permit = guard.acquire().await;
+
+        let waiting = tokio::time::timeout(MEMORY_POLL * 2, guard.acquire()).await;
+        assert!(waiting.is_err());
+        assert_eq!(guard.running.load(Ordering::SeqCst), 1);
+
+        drop(permit);
+        let _permit = guard.acquire().await;
+        assert_eq!(guard.running.load(Ordering::SeqCst), 1);
+    }
+}
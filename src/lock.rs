@@ -1,7 +1,9 @@
 use std::{
+    collections::BTreeSet,
     fs,
     path::{Path, PathBuf},
     process,
+    sync::Mutex,
 };
 
 use sysinfo::{Pid, ProcessesToUpdate, System};
@@ -9,6 +11,23 @@ use tracing::info;
 
 use crate::{consts::TSDL_LOCK_FILE, error::TsdlError, TsdlResult};
 
+/// The lock files this process holds right now.
+static HELD: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+
+/// Remove every lock file this process holds.
+///
+/// For exits that skip destructors, where the [`LockGuard`]s never drop.
+pub fn release_all() {
+    for lock in std::mem::take(&mut *held()) {
+        let _ = fs::remove_file(lock);
+    }
+}
+
+fn held() -> std::sync::MutexGuard<'static, BTreeSet<PathBuf>> {
+    HELD.lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
 /// Result of checking lock status
 #[derive(Debug)]
 pub enum LockStatus {
@@ -24,7 +43,7 @@ pub enum LockStatus {
     Unknown { pid: Pid, reason: String },
 }
 
-/// A guard that holds an exclusive lock on the build directory.
+/// A guard that holds an exclusive lock on a directory.
 /// The lock is automatically released when this guard is dropped.
 #[derive(Debug)]
 pub struct LockGuard {
@@ -33,6 +52,7 @@ pub struct LockGuard {
 
 impl Drop for LockGuard {
     fn drop(&mut self) {
+        held().remove(&self.lock);
         let _ = fs::remove_file(&self.lock);
     }
 }
@@ -45,9 +65,9 @@ pub struct Lock {
 
 impl Lock {
     #[must_use]
-    pub fn new(build_dir: &Path) -> Self {
+    pub fn new(dir: &Path) -> Self {
         Self {
-            lock_path: build_dir.join(TSDL_LOCK_FILE),
+            lock_path: dir.join(TSDL_LOCK_FILE),
             current_pid: Pid::from(process::id() as usize),
         }
     }
@@ -56,13 +76,14 @@ impl Lock {
     fn acquire(&self) -> TsdlResult<LockGuard> {
         if let Some(parent) = self.lock_path.parent() {
             fs::create_dir_all(parent).map_err(|e| {
-                TsdlError::context(format!("Creating build directory {}", parent.display()), e)
+                TsdlError::context(format!("Creating directory {}", parent.display()), e)
             })?;
         }
 
         self.write()?;
 
-        info!("Acquired lock on build directory");
+        info!("Acquired lock {}", self.lock_path.display());
+        held().insert(self.lock_path.clone());
         Ok(LockGuard {
             lock: self.lock_path.clone(),
         })
@@ -76,7 +97,7 @@ impl Lock {
         self.acquire()
     }
 
-    /// Force unlock the build directory by removing the lock file.
+    /// Force unlock the directory by removing the lock file.
     ///
     /// This does not verify ownership.
     pub fn force_unlock(&self) -> TsdlResult<()> {
@@ -87,7 +108,7 @@ impl Lock {
                     e,
                 )
             })?;
-            info!("Lock removed from build directory");
+            info!("Removed lock {}", self.lock_path.display());
         } else {
             info!("No lock file found");
         }
@@ -28,13 +28,23 @@ fn main() -> ExitCode {
 
 fn run(app: &mut App, args: &args::Args) -> TsdlResult<()> {
     match &args.command {
+        args::Command::Build(command) if command.dry_run => tsdl::build::run(app),
         args::Command::Build(_) => {
             let (result, duration) = time(|| tsdl::build::run(app));
             println!("Done in {duration}");
             result
         }
+        args::Command::Cache { command } => tsdl::cache::run(app, command),
         args::Command::Config { command } => tsdl::config::run(app, command),
+        args::Command::Pack {
+            format,
+            output,
+            queries,
+        } => tsdl::pack::pack(app, *format, output.as_deref(), *queries),
+        args::Command::Rollback => tsdl::install::rollback(app),
         args::Command::Selfupdate => selfupdate(app),
+        args::Command::Unpack { archive, force } => tsdl::pack::unpack(app, archive, *force),
+        args::Command::Verify { repair } => tsdl::verify::run(app, *repair),
     }
 }
 
@@ -0,0 +1,180 @@
+//! `out-dir/<TSDL_MANIFEST_FILE>`: what's installed in `out-dir`, for
+//! whoever loads the parsers, so they don't have to guess from file names.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{args::InstallMode, consts::TSDL_MANIFEST_FILE, error::TsdlError, TsdlResult};
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct Manifest {
+    /// Keyed by `language/grammar`, like the cache.
+    #[serde(default)]
+    pub grammars: BTreeMap<String, Grammar>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct Grammar {
+    pub language: String,
+    pub grammar: String,
+    pub repo: String,
+    #[serde(rename = "ref")]
+    pub git_ref: String,
+    pub commit: Option<String>,
+    pub tree_sitter: String,
+    /// The ABI of the generated parser.
+    pub abi: Option<u32>,
+    pub scope: Option<String>,
+    #[serde(default)]
+    pub file_types: Vec<String>,
+    /// The SPDX identifier of the grammar's license, when known.
+    #[serde(default)]
+    pub license: Option<String>,
+    /// Keyed by target: `native`, `wasm`, `static`, `object`, or a triple.
+    pub artifacts: BTreeMap<String, Artifact>,
+    /// Where its queries are, relative to `out-dir`, when they're installed.
+    #[serde(default)]
+    pub queries: Option<PathBuf>,
+    /// Everything the grammar installs, artifacts included, relative to
+    /// `out-dir`; a directory stands for what's in it.
+    #[serde(default)]
+    pub files: Vec<PathBuf>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct Artifact {
+    /// Relative to `out-dir`.
+    pub path: PathBuf,
+    pub sha256: String,
+    /// What it was installed from, in `build-dir`.
+    #[serde(default)]
+    pub source: Option<PathBuf>,
+    /// How it was installed from `source`: `auto` is recorded as what it
+    /// turned out to be, `hardlink` or `copy`.
+    #[serde(default)]
+    pub mode: Option<InstallMode>,
+}
+
+impl Manifest {
+    #[must_use]
+    pub fn file(out_dir: &Path) -> PathBuf {
+        out_dir.join(TSDL_MANIFEST_FILE)
+    }
+
+    /// Load the manifest in `out_dir`, or an empty one.
+    pub fn load(out_dir: &Path) -> TsdlResult<Self> {
+        let file = Self::file(out_dir);
+        if !file.exists() {
+            return Ok(Self::default());
+        }
+
+        let contents = fs::read_to_string(&file).map_err(|e| {
+            TsdlError::context(format!("Reading manifest at {}", file.display()), e)
+        })?;
+        serde_json::from_str(&contents)
+            .map_err(|e| TsdlError::context(format!("Parsing manifest at {}", file.display()), e))
+    }
+
+    /// Record `grammars`, replacing what was recorded for them; the other
+    /// grammars are kept.
+    pub fn update(&mut self, grammars: impl IntoIterator<Item = Grammar>) {
+        for grammar in grammars {
+            let key = format!("{}/{}", grammar.language, grammar.grammar);
+            self.grammars.insert(key, grammar);
+        }
+    }
+
+    pub fn to_json(&self) -> TsdlResult<String> {
+        let mut json = serde_json::to_string_pretty(self)?;
+        json.push('\n');
+        Ok(json)
+    }
+}
+
+/// The ABI a generated `parser.c` was generated for.
+#[must_use]
+pub fn abi(parser_c: &str) -> Option<u32> {
+    parser_c.lines().find_map(|line| {
+        line.strip_prefix("#define LANGUAGE_VERSION ")
+            .and_then(|version| version.trim().parse().ok())
+    })
+}
+
+#[must_use]
+pub fn sha256_of(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grammar(language: &str, name: &str, commit: &str) -> Grammar {
+        Grammar {
+            language: language.to_string(),
+            grammar: name.to_string(),
+            repo: format!("https://github.com/tree-sitter/tree-sitter-{language}"),
+            git_ref: "master".to_string(),
+            commit: Some(commit.to_string()),
+            tree_sitter: "0.26.5".to_string(),
+            abi: Some(15),
+            scope: None,
+            file_types: Vec::new(),
+            license: None,
+            artifacts: BTreeMap::new(),
+            queries: None,
+            files: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_update_keeps_others() {
+        let mut manifest = Manifest::default();
+        manifest.update([
+            grammar("typescript", "tsx", "a"),
+            grammar("typescript", "typescript", "a"),
+            grammar("json", "json", "a"),
+        ]);
+        manifest.update([grammar("typescript", "tsx", "b")]);
+
+        assert_eq!(manifest.grammars.len(), 3);
+        assert_eq!(
+            manifest.grammars["typescript/tsx"].commit.as_deref(),
+            Some("b")
+        );
+        assert_eq!(
+            manifest.grammars["typescript/typescript"].commit.as_deref(),
+            Some("a")
+        );
+    }
+
+    #[test]
+    fn test_abi() {
+        let parser_c = "#include \"tree_sitter/parser.h\"\n\n#define LANGUAGE_VERSION 14\n#define STATE_COUNT 10\n";
+        assert_eq!(abi(parser_c), Some(14));
+        assert_eq!(abi(""), None);
+    }
+
+    #[tokio::test]
+    async fn test_sha256() {
+        use crate::cache::hash_file;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let file = tmp.path().join("parser.so");
+        tokio::fs::write(&file, b"parser").await.unwrap();
+        assert_eq!(
+            hash_file::<Sha256>(&file).await.unwrap(),
+            "b17d45121150928f2146af49e195eff1eef5d67325be273a733fb74acadaa342"
+        );
+        assert_eq!(
+            sha256_of(b"parser"),
+            hash_file::<Sha256>(&file).await.unwrap()
+        );
+    }
+}
@@ -0,0 +1,763 @@
+//! `tsdl pack` and `tsdl unpack`: the parsers of `out-dir` and their
+//! manifest in one archive, to hand over and install elsewhere.
+//!
+//! Archives list their files in order, with zeroed timestamps and owners,
+//! so packing the same parsers twice makes the same archive.
+
+use std::{
+    collections::BTreeSet,
+    fs as std_fs,
+    io::{self, Cursor, Read, Write},
+    os::unix::fs::{symlink, PermissionsExt},
+    path::{Component, Path, PathBuf},
+};
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use zip::{
+    result::ZipResult, write::SimpleFileOptions, CompressionMethod, DateTime, ZipArchive, ZipWriter,
+};
+
+use crate::{
+    app::App,
+    args::{ArchiveFormat, InstallCommit, InstallMode},
+    build,
+    consts::TSDL_MANIFEST_FILE,
+    error::TsdlError,
+    install::{self, Transaction},
+    manifest::{self, Manifest},
+    TsdlResult,
+};
+
+/// The `zstd` level of `.tar.zst` archives.
+const ZSTD_LEVEL: i32 = 19;
+
+/// A file of an archive.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Member {
+    /// Relative to `out-dir`.
+    pub path: PathBuf,
+    /// Permission bits.
+    pub mode: u32,
+    pub contents: Contents,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Contents {
+    File(Vec<u8>),
+    /// Its target, relative to it, like a versioned library's links.
+    Symlink(PathBuf),
+}
+
+pub fn pack(
+    app: &App,
+    format: ArchiveFormat,
+    output: Option<&Path>,
+    queries: bool,
+) -> TsdlResult<()> {
+    let out_dir = &app.command.out_dir;
+    let output = output.map_or_else(
+        || PathBuf::from(format!("{}.{}", out_dir.display(), format.ext())),
+        Path::to_path_buf,
+    );
+
+    let manifest = packed(&Manifest::load(out_dir)?, queries);
+    if manifest.grammars.is_empty() {
+        return Err(TsdlError::message(format!(
+            "Nothing to pack: {} lists no parsers in {}",
+            TSDL_MANIFEST_FILE,
+            out_dir.display()
+        )));
+    }
+    let members = collect(out_dir, &manifest)?;
+    write(format, &members, &output)?;
+
+    println!(
+        "Packed {} grammars from {} into {}",
+        manifest.grammars.len(),
+        out_dir.display(),
+        output.display()
+    );
+    Ok(())
+}
+
+pub fn unpack(app: &App, archive: &Path, force: bool) -> TsdlResult<()> {
+    let out_dir = &app.command.out_dir;
+    let format = ArchiveFormat::of(archive).ok_or_else(|| {
+        TsdlError::message(format!(
+            "Can't tell the format of {}: expected .tar.zst, .tar.gz or .zip",
+            archive.display()
+        ))
+    })?;
+
+    let members = read(format, archive)?;
+    let manifest = check(archive, &members)?;
+
+    let _guard = build::lock(app)?;
+    let mut transaction = Transaction::new(out_dir, InstallCommit::Run)?;
+    let staged = stage(out_dir, &manifest, &members, force);
+    let count = manifest.grammars.len();
+    for grammar in manifest.grammars.into_values() {
+        transaction.record(grammar);
+    }
+    transaction.finish(staged.is_ok())?;
+    staged?;
+
+    println!(
+        "Unpacked {count} grammars from {} into {}",
+        archive.display(),
+        out_dir.display()
+    );
+    Ok(())
+}
+
+/// The manifest as it goes in an archive: without where things were built,
+/// and without queries unless they're packed.
+fn packed(manifest: &Manifest, queries: bool) -> Manifest {
+    let mut manifest = manifest.clone();
+    for grammar in manifest.grammars.values_mut() {
+        // Unpacking writes them as files of their own.
+        for artifact in grammar.artifacts.values_mut() {
+            artifact.source = None;
+            artifact.mode = Some(InstallMode::Copy);
+        }
+        if !queries {
+            if let Some(dir) = grammar.queries.take() {
+                grammar.files.retain(|file| !file.starts_with(&dir));
+            }
+        }
+    }
+    manifest
+}
+
+/// The files `manifest` lists in `out_dir`, and the manifest itself.
+fn collect(out_dir: &Path, manifest: &Manifest) -> TsdlResult<Vec<Member>> {
+    let mut paths = BTreeSet::new();
+    for (key, grammar) in &manifest.grammars {
+        for file in &grammar.files {
+            let path = out_dir.join(file);
+            match std_fs::symlink_metadata(&path) {
+                Ok(meta) if meta.is_dir() => {
+                    paths.extend(install::files(&path)?.into_iter().map(|f| file.join(f)));
+                }
+                Ok(_) => {
+                    paths.insert(file.clone());
+                }
+                Err(_) => {
+                    return Err(TsdlError::message(format!(
+                        "{key}: {} is missing; see tsdl verify",
+                        path.display()
+                    )))
+                }
+            }
+        }
+    }
+
+    let mut members = vec![Member {
+        path: PathBuf::from(TSDL_MANIFEST_FILE),
+        mode: 0o644,
+        contents: Contents::File(manifest.to_json()?.into_bytes()),
+    }];
+    for path in paths {
+        members.push(member(out_dir, path)?);
+    }
+    Ok(members)
+}
+
+/// `path`, in `out_dir`, as a member of an archive. Symlinks into
+/// `build-dir`, from `install-mode = "symlink"`, are packed as the files
+/// they point to.
+fn member(out_dir: &Path, path: PathBuf) -> TsdlResult<Member> {
+    let full = out_dir.join(&path);
+    let context = |e| TsdlError::context(format!("Reading {}", full.display()), e);
+
+    if let Ok(target) = std_fs::read_link(&full) {
+        if target.is_relative() {
+            return Ok(Member {
+                path,
+                mode: 0o777,
+                contents: Contents::Symlink(target),
+            });
+        }
+    }
+
+    let executable = std_fs::metadata(&full)
+        .map_err(context)?
+        .permissions()
+        .mode()
+        & 0o111
+        != 0;
+    Ok(Member {
+        path,
+        mode: if executable { 0o755 } else { 0o644 },
+        contents: Contents::File(std_fs::read(&full).map_err(context)?),
+    })
+}
+
+fn write(format: ArchiveFormat, members: &[Member], output: &Path) -> TsdlResult<()> {
+    let context = |e| TsdlError::context(format!("Writing {}", output.display()), e);
+    if let Some(dir) = output.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std_fs::create_dir_all(dir).map_err(context)?;
+    }
+
+    let archive = match format {
+        ArchiveFormat::Zip => zip(members).map_err(io::Error::from).map_err(context)?,
+        ArchiveFormat::TarGz => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+            encoder
+                .write_all(&tar(members).map_err(context)?)
+                .map_err(context)?;
+            encoder.finish().map_err(context)?
+        }
+        ArchiveFormat::TarZst => {
+            zstd::encode_all(tar(members).map_err(context)?.as_slice(), ZSTD_LEVEL)
+                .map_err(context)?
+        }
+    };
+    std_fs::write(output, archive).map_err(context)
+}
+
+fn read(format: ArchiveFormat, archive: &Path) -> TsdlResult<Vec<Member>> {
+    let context = |e| TsdlError::context(format!("Reading {}", archive.display()), e);
+    let bytes = std_fs::read(archive).map_err(context)?;
+
+    match format {
+        ArchiveFormat::Zip => unzip(&bytes).map_err(io::Error::from).map_err(context),
+        ArchiveFormat::TarGz => {
+            let mut tar = Vec::new();
+            GzDecoder::new(bytes.as_slice())
+                .read_to_end(&mut tar)
+                .map_err(context)?;
+            untar(&tar).map_err(context)
+        }
+        ArchiveFormat::TarZst => {
+            let tar = zstd::decode_all(bytes.as_slice()).map_err(context)?;
+            untar(&tar).map_err(context)
+        }
+    }
+}
+
+fn tar(members: &[Member]) -> io::Result<Vec<u8>> {
+    let mut builder = tar::Builder::new(Vec::new());
+    for member in members {
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(member.mode);
+        header.set_mtime(0);
+        header.set_uid(0);
+        header.set_gid(0);
+        match &member.contents {
+            Contents::File(data) => {
+                header.set_entry_type(tar::EntryType::Regular);
+                header.set_size(data.len() as u64);
+                builder.append_data(&mut header, &member.path, data.as_slice())?;
+            }
+            Contents::Symlink(target) => {
+                header.set_entry_type(tar::EntryType::Symlink);
+                header.set_size(0);
+                builder.append_link(&mut header, &member.path, target)?;
+            }
+        }
+    }
+    builder.into_inner()
+}
+
+fn untar(bytes: &[u8]) -> io::Result<Vec<Member>> {
+    let mut members = Vec::new();
+    for entry in tar::Archive::new(bytes).entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let mode = entry.header().mode()?;
+        let contents = match entry.header().entry_type() {
+            tar::EntryType::Directory => continue,
+            tar::EntryType::Symlink => {
+                let target = entry.link_name()?.ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, "symlink without a target")
+                })?;
+                Contents::Symlink(target.into_owned())
+            }
+            tar::EntryType::Regular => {
+                let mut data = Vec::new();
+                entry.read_to_end(&mut data)?;
+                Contents::File(data)
+            }
+            other => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: unsupported entry type {other:?}", path.display()),
+                ))
+            }
+        };
+        members.push(Member {
+            path,
+            mode,
+            contents,
+        });
+    }
+    Ok(members)
+}
+
+/// Deflated files and stored symlinks, with unix modes; every entry is
+/// dated 1980-01-01, the earliest a zip can say.
+fn zip(members: &[Member]) -> ZipResult<Vec<u8>> {
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    for member in members {
+        let options = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .compression_level(Some(9))
+            .last_modified_time(DateTime::default())
+            .unix_permissions(member.mode);
+        let name = member.path.to_string_lossy();
+        match &member.contents {
+            Contents::File(data) => {
+                writer.start_file(name, options)?;
+                writer.write_all(data)?;
+            }
+            Contents::Symlink(target) => {
+                writer.add_symlink(name, target.to_string_lossy(), options)?;
+            }
+        }
+    }
+    Ok(writer.finish()?.into_inner())
+}
+
+fn unzip(bytes: &[u8]) -> ZipResult<Vec<Member>> {
+    let mut archive = ZipArchive::new(Cursor::new(bytes))?;
+    let mut members = Vec::new();
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)?;
+        if file.is_dir() {
+            continue;
+        }
+        let path = PathBuf::from(file.name());
+        let mode = file.unix_mode().unwrap_or(0o644) & 0o7777;
+        let is_symlink = file.is_symlink();
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        let contents = if is_symlink {
+            Contents::Symlink(PathBuf::from(String::from_utf8_lossy(&data).into_owned()))
+        } else {
+            Contents::File(data)
+        };
+        members.push(Member {
+            path,
+            mode,
+            contents,
+        });
+    }
+    Ok(members)
+}
+
+/// The manifest of `archive`, once every file stays inside `out-dir` and
+/// every artifact has the checksum the manifest says.
+fn check(archive: &Path, members: &[Member]) -> TsdlResult<Manifest> {
+    let invalid = |why: String| TsdlError::message(format!("{}: {why}", archive.display()));
+
+    let manifest = members
+        .iter()
+        .find(|member| member.path == Path::new(TSDL_MANIFEST_FILE))
+        .and_then(|member| match &member.contents {
+            Contents::File(data) => Some(data),
+            Contents::Symlink(_) => None,
+        })
+        .ok_or_else(|| {
+            invalid(format!(
+                "no {TSDL_MANIFEST_FILE}; was it made by tsdl pack?"
+            ))
+        })?;
+    let manifest: Manifest = serde_json::from_slice(manifest)
+        .map_err(|e| TsdlError::context(format!("Parsing {TSDL_MANIFEST_FILE}"), e))?;
+
+    for member in members {
+        if !is_plain(&member.path) {
+            return Err(invalid(format!(
+                "{} would go outside out-dir",
+                member.path.display()
+            )));
+        }
+        // A versioned library's links point next to them, nowhere else.
+        if let Contents::Symlink(target) = &member.contents {
+            if !is_plain(target) || target.components().count() != 1 {
+                return Err(invalid(format!(
+                    "{} links outside its directory, to {}",
+                    member.path.display(),
+                    target.display()
+                )));
+            }
+        }
+        let through = members.iter().find(|link| {
+            matches!(link.contents, Contents::Symlink(_))
+                && member.path != link.path
+                && member.path.starts_with(&link.path)
+        });
+        if let Some(link) = through {
+            return Err(invalid(format!(
+                "{} would be written through the symlink {}",
+                member.path.display(),
+                link.path.display()
+            )));
+        }
+    }
+
+    // Languages name staging directories, and files and queries are where
+    // members go: none of them can lead out of `out-dir`.
+    for (key, grammar) in &manifest.grammars {
+        for name in [&grammar.language, &grammar.grammar] {
+            if !is_name(name) {
+                return Err(invalid(format!("{key}: {name:?} isn't a plain name")));
+            }
+        }
+        let paths = grammar
+            .files
+            .iter()
+            .chain(&grammar.queries)
+            .chain(grammar.artifacts.values().map(|artifact| &artifact.path));
+        for path in paths {
+            if !is_plain(path) || path.components().next().is_none() {
+                return Err(invalid(format!(
+                    "{key}: {} would go outside out-dir",
+                    path.display()
+                )));
+            }
+        }
+    }
+
+    for (key, grammar) in &manifest.grammars {
+        for artifact in grammar.artifacts.values() {
+            let data = members
+                .iter()
+                .find(|member| member.path == artifact.path)
+                .and_then(|member| match &member.contents {
+                    Contents::File(data) => Some(data),
+                    Contents::Symlink(_) => None,
+                })
+                .ok_or_else(|| invalid(format!("{key}: {} is missing", artifact.path.display())))?;
+            if manifest::sha256_of(data) != artifact.sha256 {
+                return Err(invalid(format!(
+                    "{key}: {} doesn't match its checksum",
+                    artifact.path.display()
+                )));
+            }
+        }
+    }
+
+    Ok(manifest)
+}
+
+/// Whether `path` only goes down: no root, no `..`.
+fn is_plain(path: &Path) -> bool {
+    path.components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+}
+
+/// Whether `name` is one plain path component, like a language's.
+fn is_name(name: &str) -> bool {
+    let mut components = Path::new(name).components();
+    matches!(components.next(), Some(Component::Normal(part)) if part == name)
+        && components.next().is_none()
+}
+
+/// Stage `members` for the grammars of `manifest` that install them. Files
+/// go before symlinks, and nothing is written through a symlink. Like a
+/// build, a file that differs from the one in `out_dir` needs `force`.
+fn stage(out_dir: &Path, manifest: &Manifest, members: &[Member], force: bool) -> TsdlResult<()> {
+    let (files, links): (Vec<_>, Vec<_>) = members
+        .iter()
+        .partition(|member| matches!(member.contents, Contents::File(_)));
+    for member in files.into_iter().chain(links) {
+        if member.path == Path::new(TSDL_MANIFEST_FILE) {
+            continue;
+        }
+        let grammar = manifest
+            .grammars
+            .values()
+            .find(|grammar| grammar.files.iter().any(|f| member.path.starts_with(f)))
+            .ok_or_else(|| {
+                TsdlError::message(format!(
+                    "{} isn't in the archive's {TSDL_MANIFEST_FILE}",
+                    member.path.display()
+                ))
+            })?;
+
+        let staging = install::staging_dir(out_dir, &grammar.language);
+        let staged = staging.join(&member.path);
+        let context = |e| TsdlError::context(format!("Staging {}", staged.display()), e);
+        let linked = staged
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| *dir != staging)
+            .find(|dir| std_fs::symlink_metadata(dir).is_ok_and(|m| m.is_symlink()));
+        if let Some(dir) = linked {
+            return Err(TsdlError::message(format!(
+                "{} would be written through the symlink {}",
+                member.path.display(),
+                dir.display()
+            )));
+        }
+        if let Some(dir) = staged.parent() {
+            std_fs::create_dir_all(dir).map_err(context)?;
+        }
+        match &member.contents {
+            Contents::File(data) => {
+                if !force && differs(&out_dir.join(&member.path), data)? {
+                    return Err(TsdlError::message(format!(
+                        "Binary differs at {}. Use --force to overwrite",
+                        out_dir.join(&member.path).display()
+                    )));
+                }
+                std_fs::write(&staged, data).map_err(context)?;
+                std_fs::set_permissions(&staged, std_fs::Permissions::from_mode(member.mode))
+                    .map_err(context)?;
+            }
+            Contents::Symlink(target) => symlink(target, &staged).map_err(context)?,
+        }
+    }
+    Ok(())
+}
+
+/// Whether there's a file at `dst` that isn't `data`.
+fn differs(dst: &Path, data: &[u8]) -> TsdlResult<bool> {
+    match std_fs::read(dst) {
+        Ok(existing) => Ok(manifest::sha256_of(&existing) != manifest::sha256_of(data)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(TsdlError::context(format!("Reading {}", dst.display()), e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    fn setup() -> (tempfile::TempDir, PathBuf, Manifest) {
+        let tmp = tempfile::tempdir().unwrap();
+        let out_dir = tmp.path().join("parsers");
+        let queries = out_dir.join("queries").join("json");
+        std_fs::create_dir_all(&queries).unwrap();
+        std_fs::write(out_dir.join("libtree-sitter-json.so.0.21.0"), b"parser").unwrap();
+        symlink(
+            "libtree-sitter-json.so.0.21.0",
+            out_dir.join("libtree-sitter-json.so"),
+        )
+        .unwrap();
+        std_fs::write(queries.join("highlights.scm"), "(string) @string").unwrap();
+
+        let artifact = manifest::Artifact {
+            path: PathBuf::from("libtree-sitter-json.so.0.21.0"),
+            sha256: manifest::sha256_of(b"parser"),
+            source: Some(tmp.path().join("build").join("libtree-sitter-json.so")),
+            mode: Some(InstallMode::Hardlink),
+        };
+        let mut manifest = Manifest::default();
+        manifest.update([manifest::Grammar {
+            language: "json".to_string(),
+            grammar: "json".to_string(),
+            repo: "https://github.com/tree-sitter/tree-sitter-json".to_string(),
+            git_ref: "v0.21.0".to_string(),
+            commit: None,
+            tree_sitter: "0.26.5".to_string(),
+            abi: Some(14),
+            scope: Some("source.json".to_string()),
+            file_types: vec!["json".to_string()],
+            license: None,
+            artifacts: BTreeMap::from([("native".to_string(), artifact)]),
+            queries: Some(PathBuf::from("queries/json")),
+            files: vec![
+                PathBuf::from("libtree-sitter-json.so"),
+                PathBuf::from("libtree-sitter-json.so.0.21.0"),
+                PathBuf::from("queries/json"),
+            ],
+        }]);
+        (tmp, out_dir, manifest)
+    }
+
+    fn paths(members: &[Member]) -> Vec<&Path> {
+        members.iter().map(|m| m.path.as_path()).collect()
+    }
+
+    #[test]
+    fn test_collect() {
+        let (_tmp, out_dir, manifest) = setup();
+
+        let members = collect(&out_dir, &packed(&manifest, false)).unwrap();
+        assert_eq!(
+            paths(&members),
+            [
+                Path::new(TSDL_MANIFEST_FILE),
+                Path::new("libtree-sitter-json.so"),
+                Path::new("libtree-sitter-json.so.0.21.0"),
+            ]
+        );
+        assert_eq!(
+            members[1].contents,
+            Contents::Symlink(PathBuf::from("libtree-sitter-json.so.0.21.0"))
+        );
+
+        let members = collect(&out_dir, &packed(&manifest, true)).unwrap();
+        assert_eq!(
+            members.last().unwrap().path,
+            Path::new("queries/json/highlights.scm")
+        );
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let (tmp, out_dir, manifest) = setup();
+        let members = collect(&out_dir, &packed(&manifest, true)).unwrap();
+
+        for format in [
+            ArchiveFormat::TarZst,
+            ArchiveFormat::TarGz,
+            ArchiveFormat::Zip,
+        ] {
+            let archive = tmp.path().join(format!("parsers.{}", format.ext()));
+            write(format, &members, &archive).unwrap();
+            let first = std_fs::read(&archive).unwrap();
+            write(format, &members, &archive).unwrap();
+            assert_eq!(first, std_fs::read(&archive).unwrap(), "{format:?}");
+
+            assert_eq!(ArchiveFormat::of(&archive), Some(format));
+            let read = read(format, &archive).unwrap();
+            assert_eq!(read, members, "{format:?}");
+            assert!(check(&archive, &read).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        let (_tmp, out_dir, manifest) = setup();
+        let archive = Path::new("parsers.zip");
+        let mut members = collect(&out_dir, &packed(&manifest, false)).unwrap();
+        assert!(check(archive, &members).is_ok());
+
+        members[2].contents = Contents::File(b"tampered".to_vec());
+        let err = check(archive, &members).unwrap_err();
+        assert!(err.to_string().contains("doesn't match its checksum"));
+
+        members.push(Member {
+            path: PathBuf::from("../evil.so"),
+            mode: 0o644,
+            contents: Contents::File(Vec::new()),
+        });
+        let err = check(archive, &members).unwrap_err();
+        assert!(err.to_string().contains("outside out-dir"));
+
+        assert!(check(archive, &members[1..]).is_err());
+    }
+
+    #[test]
+    fn test_check_symlinks() {
+        let (_tmp, out_dir, manifest) = setup();
+        let archive = Path::new("parsers.zip");
+        let members = collect(&out_dir, &packed(&manifest, false)).unwrap();
+
+        for target in ["/etc", "../parsers.so", "queries/../../x"] {
+            let mut evil = members.clone();
+            evil.push(Member {
+                path: PathBuf::from("queries/json/a"),
+                mode: 0o777,
+                contents: Contents::Symlink(PathBuf::from(target)),
+            });
+            let err = check(archive, &evil).unwrap_err();
+            assert!(err.to_string().contains("links outside"), "{target}");
+        }
+
+        let mut evil = members;
+        evil.push(Member {
+            path: PathBuf::from("queries/json/a"),
+            mode: 0o777,
+            contents: Contents::Symlink(PathBuf::from("b")),
+        });
+        evil.push(Member {
+            path: PathBuf::from("queries/json/a/x"),
+            mode: 0o644,
+            contents: Contents::File(Vec::new()),
+        });
+        let err = check(archive, &evil).unwrap_err();
+        assert!(err.to_string().contains("through the symlink"));
+    }
+
+    #[test]
+    fn test_stage_refuses_symlinked_dirs() {
+        let (tmp, out_dir, manifest) = setup();
+        let other = tmp.path().join("other");
+        let outside = tmp.path().join("outside");
+        std_fs::create_dir_all(&outside).unwrap();
+        let staging = install::staging_dir(&other, "json");
+        std_fs::create_dir_all(&staging).unwrap();
+        symlink(&outside, staging.join("queries")).unwrap();
+
+        let members = collect(&out_dir, &packed(&manifest, true)).unwrap();
+        let err = stage(&other, &manifest, &members, false).unwrap_err();
+        assert!(err.to_string().contains("through the symlink"));
+        assert!(std_fs::read_dir(&outside).unwrap().next().is_none());
+    }
+
+    #[test]
+    fn test_stage() {
+        let (tmp, out_dir, manifest) = setup();
+        let members = collect(&out_dir, &packed(&manifest, true)).unwrap();
+        let other = tmp.path().join("other");
+
+        let mut transaction = Transaction::new(&other, InstallCommit::Run).unwrap();
+        stage(&other, &manifest, &members, false).unwrap();
+        for grammar in manifest.grammars.into_values() {
+            transaction.record(grammar);
+        }
+        transaction.finish(true).unwrap();
+
+        assert_eq!(
+            std_fs::read(other.join("libtree-sitter-json.so")).unwrap(),
+            b"parser"
+        );
+        assert!(other.join("queries/json/highlights.scm").exists());
+        assert!(Manifest::load(&other)
+            .unwrap()
+            .grammars
+            .contains_key("json/json"));
+    }
+
+    #[test]
+    fn test_stage_differs_needs_force() {
+        let (tmp, out_dir, manifest) = setup();
+        let members = collect(&out_dir, &packed(&manifest, false)).unwrap();
+        let other = tmp.path().join("other");
+        std_fs::create_dir_all(&other).unwrap();
+
+        std_fs::write(other.join("libtree-sitter-json.so.0.21.0"), b"parser").unwrap();
+        stage(&other, &manifest, &members, false).unwrap();
+        std_fs::remove_dir_all(install::staging_dir(&other, "json")).unwrap();
+
+        std_fs::write(other.join("libtree-sitter-json.so.0.21.0"), b"other!").unwrap();
+        let err = stage(&other, &manifest, &members, false).unwrap_err();
+        assert!(err.to_string().contains("Binary differs"));
+        std_fs::remove_dir_all(install::staging_dir(&other, "json")).unwrap();
+        stage(&other, &manifest, &members, true).unwrap();
+    }
+
+    #[test]
+    fn test_check_manifest_paths() {
+        let (_tmp, out_dir, manifest) = setup();
+        let archive = Path::new("parsers.zip");
+        let packed = packed(&manifest, true);
+
+        let evil: [fn(&mut manifest::Grammar); 5] = [
+            |g| g.language = "../../../home/u/.config".to_string(),
+            |g| g.grammar = "a/b".to_string(),
+            |g| g.files.push(PathBuf::from("../outside")),
+            |g| g.files.push(PathBuf::from("/etc/passwd")),
+            |g| g.queries = Some(PathBuf::from("queries/../../x")),
+        ];
+        for tamper in evil {
+            let mut manifest = packed.clone();
+            tamper(manifest.grammars.get_mut("json/json").unwrap());
+            let mut members = collect(&out_dir, &packed).unwrap();
+            members[0].contents = Contents::File(manifest.to_json().unwrap().into_bytes());
+
+            let err = check(archive, &members).unwrap_err().to_string();
+            assert!(
+                err.contains("isn't a plain name") || err.contains("outside out-dir"),
+                "{err}"
+            );
+        }
+    }
+}
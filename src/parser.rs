@@ -1,21 +1,32 @@
 use std::{
+    collections::{BTreeMap, BTreeSet},
     env::consts::DLL_EXTENSION,
-    os::unix::fs::MetadataExt,
     path::{Path, PathBuf},
     sync::Arc,
 };
 
+use sha2::Sha256;
 use tokio::{fs, process::Command};
-use tracing::warn;
+use tracing::{debug, warn};
 
 use crate::{
     actors::ProgressAddr,
+    args::{Generate, Profile, Strip, Target},
     build::{BuildContext, BuildSpec, OutputConfig},
-    cache::{Entry, Update},
+    cache::{self, Entry, Sources, Update},
+    cc::{self, Toolchain},
+    dev_files,
     error::{self, TsdlError},
-    git::clone_fast,
-    sh::{Exec, Script},
-    walk::collect_grammar_paths,
+    git::{self, clone_fast},
+    install::{self, Installed},
+    layout,
+    license::License,
+    limits::MemoryGuard,
+    manifest, sandbox,
+    sh::{self, Exec},
+    soname::{self, Chain},
+    tree_sitter_json::TreeSitterJson,
+    walk::{self, collect_grammar_paths, collect_manifest_grammars},
     TsdlResult,
 };
 
@@ -35,19 +46,25 @@ pub struct GrammarBuild {
     pub context: BuildContext,
     pub dir: Arc<PathBuf>,
     pub entry: Option<Entry>,
+    pub files: Arc<Sources>,
     pub hash: Arc<str>,
     pub language: Arc<str>, // Required for error reporting and cache keys; set from parent LanguageBuild
+    /// The checkout's license, shared by the language's grammars.
+    pub license: Arc<License>,
     pub name: Arc<str>,
     pub output: OutputConfig,
     pub progress: ProgressAddr, // Use language's handle
     pub spec: Arc<BuildSpec>,
     pub ts_cli: Arc<PathBuf>,
+    /// The checkout's version, for `versioned` and `dev-files`.
+    pub version: Option<Arc<str>>,
 }
 
 impl GrammarBuild {
     /// Build this grammar, returning a cache update if it was built.
     /// Uses the language's progress handle for progress reporting.
-    pub async fn build(&self) -> TsdlResult<Option<Update>> {
+    /// Compiles wait on `memory`, when set.
+    pub async fn build(&self, memory: Option<&MemoryGuard>) -> TsdlResult<Option<Update>> {
         self.progress.step("checking cache");
         let key = format!("{}/{}", self.language, self.name);
 
@@ -56,7 +73,7 @@ impl GrammarBuild {
 
         if hit {
             // Install the binary from the build directory
-            if let Err(e) = self.install().await {
+            if let Err(e) = self.install_with_hook().await {
                 self.progress.err("install");
                 return Err(e);
             }
@@ -75,6 +92,16 @@ impl GrammarBuild {
             return Err(err);
         }
 
+        let _permit = match memory {
+            Some(memory) => {
+                if memory.is_over_budget().await {
+                    self.progress.msg("waiting for memory");
+                }
+                Some(memory.acquire().await)
+            }
+            None => None,
+        };
+
         // Build the grammar
         if let Err(e) = self.build_grammar().await {
             self.progress.err("build");
@@ -86,6 +113,7 @@ impl GrammarBuild {
             name: key.into(),
             entry: Entry {
                 hash: self.hash.clone(),
+                files: self.files.as_ref().clone(),
                 spec: self.spec.clone(),
             },
         };
@@ -95,9 +123,16 @@ impl GrammarBuild {
         Ok(Some(update))
     }
 
-    fn build_command(&self, ext: &str, output_name: &str) -> Command {
+    fn build_command(&self, ext: &str, output_name: &str) -> TsdlResult<Command> {
         if let Some(script) = &self.spec.build_script {
-            return Command::from_str(script);
+            let mut cmd = sandbox::script(&self.context.sandbox, script, &self.dir, &[])?;
+            let target = if ext == WASM_EXTENSION {
+                Target::Wasm
+            } else {
+                Target::Native
+            };
+            self.set_env(&mut cmd, Some((target, ext)));
+            return Ok(cmd);
         }
 
         let mut cmd = Command::new(self.ts_cli.as_os_str());
@@ -108,35 +143,111 @@ impl GrammarBuild {
         }
 
         cmd.args(["--output", output_name]);
-        cmd
+        Ok(cmd)
     }
 
     async fn build_grammar(&self) -> TsdlResult<()> {
-        // Generate parser if no custom build script
+        let hooks = &self.spec.hooks;
+
         self.progress.step("generating");
-        if self.spec.build_script.is_none() {
+        if self.should_generate().await? {
+            self.run_hook("pre-generate", hooks.pre_generate.as_deref())
+                .await?;
             self.generate().await?;
-        } else {
-            warn!("Custom build scripts not supported for generate step (TypeScript limitation)");
         }
 
         // Build native and/or wasm targets
         self.progress.step("building");
+        self.run_hook("pre-build", hooks.pre_build.as_deref())
+            .await?;
         self.build_targets().await?;
+        self.run_hook("post-build", hooks.post_build.as_deref())
+            .await?;
 
         // Install built parsers
         self.progress.step("installing");
+        self.install_with_hook().await
+    }
+
+    /// Install, built or cached, then run post-install on what was staged.
+    async fn install_with_hook(&self) -> TsdlResult<()> {
         self.install().await?;
+        self.run_hook("post-install", self.spec.hooks.post_install.as_deref())
+            .await
+    }
 
-        Ok(())
+    async fn run_hook(&self, hook: &'static str, script: Option<&str>) -> TsdlResult<()> {
+        let Some(script) = script else {
+            return Ok(());
+        };
+
+        self.progress.msg(hook);
+        let hook_error = |err| {
+            error::TsdlError::Step(error::Step::new(
+                self.language.clone(),
+                error::ParserOp::Hook {
+                    hook,
+                    dir: self.dir.to_path_buf(),
+                },
+                err,
+            ))
+        };
+
+        // What was just installed is still staged, and post-install may
+        // add to it.
+        let staged = (hook == "post-install").then(|| self.staged(&self.out_dir()));
+        if let Some(staged) = &staged {
+            fs::create_dir_all(staged)
+                .await
+                .map_err(|err| hook_error(err.into()))?;
+        }
+        let writable = staged.as_deref().into_iter().collect::<Vec<_>>();
+        let mut cmd = sandbox::script(&self.context.sandbox, script, &self.dir, &writable)
+            .map_err(hook_error)?;
+        self.set_env(&mut cmd, None);
+        if let Some(staged) = &staged {
+            cmd.env("TSDL_OUT_DIR", staged);
+        }
+        cmd.env("TSDL_HOOK", hook)
+            .exec_with(self.context.step_timeout)
+            .await
+            .map(|_| ())
+            .map_err(hook_error)
+    }
+
+    /// The environment hooks and build scripts get to know what they're
+    /// building. A build script builds one `target`, with `ext`; hooks get
+    /// all of the grammar's.
+    fn set_env(&self, cmd: &mut Command, build: Option<(Target, &str)>) {
+        let (target, ext) = match build {
+            Some((target, ext)) => (target.to_string(), ext),
+            None => (self.spec.target.to_string(), DLL_EXTENSION),
+        };
+        cmd.env("TSDL_LANGUAGE", self.language.as_ref())
+            .env("TSDL_GRAMMAR", self.name.as_ref())
+            .env("TSDL_GRAMMAR_DIR", self.dir.as_os_str())
+            .env("TSDL_OUT_DIR", self.out_dir())
+            .env("TSDL_PREFIX", &self.spec.prefix)
+            .env("TSDL_BUILD_PROFILE", self.spec.profile.to_string())
+            .env("TSDL_TARGET", target)
+            .env("TSDL_EXT", ext)
+            .env("TSDL_TS_CLI", self.ts_cli.as_os_str());
+        if ext == WASM_EXTENSION {
+            return;
+        }
+        if let Some(chain) = self.chain(&self.artifact_path("native", DLL_EXTENSION)) {
+            cmd.env("TSDL_SONAME", chain.soname);
+        }
     }
 
     async fn build_target(&self, ext: &str) -> TsdlResult<()> {
         let output_name = self.parser_name_and_ext(ext);
-        let mut cmd = self.build_command(ext, &output_name);
+        let mut cmd = self
+            .build_command(ext, &output_name)
+            .map_err(|err| self.build_error(err))?;
 
         cmd.current_dir(self.dir.as_ref())
-            .exec()
+            .exec_with(self.context.step_timeout)
             .await
             .map_err(|err| {
                 error::TsdlError::Step(error::Step::new(
@@ -153,20 +264,205 @@ impl GrammarBuild {
 
     async fn build_targets(&self) -> TsdlResult<()> {
         if self.spec.target.native() {
-            self.build_target(DLL_EXTENSION).await?;
+            self.build_native().await?;
         }
 
         if self.spec.target.wasm() {
             self.build_target(WASM_EXTENSION).await?;
         }
 
+        if self.spec.target.static_lib() || self.spec.target.object() {
+            self.build_host_objects().await?;
+        }
+
+        if self.spec.target.cross() {
+            for toolchain in &self.spec.cross {
+                self.build_cross(toolchain).await?;
+            }
+        }
+
         Ok(())
     }
 
-    async fn create_hardlink(&self, src: &Path, dst: &Path) -> TsdlResult<()> {
-        fs::hard_link(src, dst).await.map_err(|e| {
-            TsdlError::context(format!("Linking {} -> {}", src.display(), dst.display()), e)
-        })
+    /// Build the host's shared library: with `tree-sitter build` (or the
+    /// build script) for release builds, with the host compilers otherwise.
+    async fn build_native(&self) -> TsdlResult<()> {
+        if self.compiles_native() {
+            let output = self
+                .work_dir()
+                .join(self.parser_name_and_ext(DLL_EXTENSION));
+            let soname = self
+                .chain(&self.artifact_path("native", DLL_EXTENSION))
+                .map(|chain| chain.soname);
+            cc::compile(
+                &Toolchain::host(),
+                self.spec.profile,
+                &self.dir,
+                &output,
+                soname.as_deref(),
+                self.context.step_timeout,
+            )
+            .await
+            .map_err(|err| self.build_error(err))?;
+        } else {
+            self.build_target(DLL_EXTENSION).await?;
+        }
+
+        let lib = self.native_binary().await?;
+        cc::strip(
+            &cc::host_objcopy(),
+            self.spec.strip,
+            &lib,
+            self.context.step_timeout,
+        )
+        .await
+        .map_err(|err| self.build_error(err))
+    }
+
+    /// `tree-sitter build` can't set a SONAME, so versioned libraries are
+    /// always compiled by tsdl.
+    fn compiles_native(&self) -> bool {
+        self.spec.build_script.is_none()
+            && (self.spec.profile != Profile::Release || self.is_versioned())
+    }
+
+    /// The names of the versioned host library installed at `dst`, with
+    /// `versioned`.
+    fn chain(&self, dst: &Path) -> Option<Chain> {
+        let file = dst.file_name()?.to_string_lossy();
+        self.version
+            .as_deref()
+            .filter(|_| self.is_versioned())
+            .map(|version| Chain::new(&file, version))
+    }
+
+    fn is_versioned(&self) -> bool {
+        is_versioned(&self.spec) && self.version.is_some()
+    }
+
+    async fn native_binary(&self) -> TsdlResult<PathBuf> {
+        if self.compiles_native() {
+            Ok(self
+                .work_dir()
+                .join(self.parser_name_and_ext(DLL_EXTENSION)))
+        } else {
+            self.find_parser_binary(DLL_EXTENSION).await
+        }
+    }
+
+    /// Where the profile's artifacts are built, so they never replace
+    /// another profile's.
+    fn work_dir(&self) -> PathBuf {
+        match self.spec.profile.subdir() {
+            Some(subdir) => self.dir.join(subdir),
+            None => self.dir.to_path_buf(),
+        }
+    }
+
+    /// Where `path`, in `out-dir`, is staged until the install commits.
+    fn staged(&self, path: &Path) -> PathBuf {
+        let staging = install::staging_dir(&self.output.out_dir, &self.language);
+        match path.strip_prefix(self.output.out_dir.as_ref()) {
+            Ok(rel) => staging.join(rel),
+            Err(_) => staging,
+        }
+    }
+
+    /// Where the profile's artifacts are installed.
+    fn out_dir(&self) -> PathBuf {
+        match self.spec.profile.subdir() {
+            Some(subdir) => self.output.out_dir.join(subdir),
+            None => self.output.out_dir.to_path_buf(),
+        }
+    }
+
+    async fn build_cross(&self, toolchain: &Toolchain) -> TsdlResult<()> {
+        self.progress
+            .msg(format!("cross-compiling {}", toolchain.triple));
+        let output = self.cross_binary(toolchain);
+
+        cc::compile(
+            toolchain,
+            self.spec.profile,
+            &self.dir,
+            &output,
+            None,
+            self.context.step_timeout,
+        )
+        .await
+        .map_err(|err| self.build_error(err))
+    }
+
+    /// Build the static library and/or relocatable object with the host's
+    /// compilers.
+    async fn build_host_objects(&self) -> TsdlResult<()> {
+        let toolchain = Toolchain::host();
+        let mut cxx = false;
+
+        if self.spec.target.static_lib() {
+            cxx |= cc::archive(
+                &toolchain,
+                &cc::host_ar(),
+                self.spec.profile,
+                &self.dir,
+                &self.static_binary(),
+                self.context.step_timeout,
+            )
+            .await
+            .map_err(|err| self.build_error(err))?;
+        }
+
+        if self.spec.target.object() {
+            cxx |= cc::relocatable(
+                &toolchain,
+                self.spec.profile,
+                &self.dir,
+                &self.object_binary(),
+                self.context.step_timeout,
+            )
+            .await
+            .map_err(|err| self.build_error(err))?;
+        }
+
+        if cxx {
+            let note = format!(
+                "{} has a C++ scanner: link libstdc++ (-lstdc++) along with it",
+                self.name
+            );
+            warn!("{note}");
+            self.progress.msg(note);
+        }
+
+        Ok(())
+    }
+
+    fn build_error(&self, err: TsdlError) -> TsdlError {
+        error::TsdlError::Step(error::Step::new(
+            self.language.clone(),
+            error::ParserOp::Build {
+                dir: self.dir.to_path_buf(),
+            },
+            err,
+        ))
+    }
+
+    fn static_binary(&self) -> PathBuf {
+        self.work_dir()
+            .join("static")
+            .join(static_name(self.parser_name_and_ext("a")))
+    }
+
+    fn object_binary(&self) -> PathBuf {
+        self.work_dir()
+            .join("object")
+            .join(self.parser_name_and_ext("o"))
+    }
+
+    /// Where a cross-compiled parser is built: `<triple>/` in the work dir.
+    fn cross_binary(&self, toolchain: &Toolchain) -> PathBuf {
+        self.work_dir()
+            .join(&toolchain.triple)
+            .join(self.parser_name_and_ext(&toolchain.ext))
     }
 
     async fn find_parser_binary(&self, ext: &str) -> TsdlResult<PathBuf> {
@@ -208,88 +504,331 @@ impl GrammarBuild {
     }
 
     async fn generate(&self) -> TsdlResult<()> {
-        Command::new(self.ts_cli.as_os_str())
-            .current_dir(self.dir.as_path())
-            .arg("generate")
-            .exec()
+        let generate_error = |err| {
+            error::TsdlError::Step(error::Step::new(
+                self.language.clone(),
+                error::ParserOp::Generate {
+                    dir: self.dir.to_path_buf(),
+                },
+                err,
+            ))
+        };
+
+        // Only grammars that do get generated need a JS runtime: cached
+        // builds and committed sources work without one.
+        let runtime = self.spec.js_runtime.as_deref().unwrap_or("node");
+        if sh::which(runtime).is_none() {
+            return Err(generate_error(TsdlError::message(format!(
+                "`{runtime}` is needed to generate parsers but was not found in PATH; \
+                 install it, set js-runtime, or set generate = \"never\" to build from the committed sources"
+            ))));
+        }
+
+        let mut cmd = Command::new(self.ts_cli.as_os_str());
+        cmd.current_dir(self.dir.as_path()).arg("generate");
+
+        if let Some(abi) = self.spec.abi {
+            cmd.args(["--abi", &abi.to_string()]);
+        }
+
+        if let Some(runtime) = &self.spec.js_runtime {
+            cmd.env("TREE_SITTER_JS_RUNTIME", runtime);
+        }
+
+        cmd.exec_with(self.context.step_timeout)
             .await
             .map(|_| ())
-            .map_err(|err| {
-                error::TsdlError::Step(error::Step::new(
-                    self.language.clone(),
-                    error::ParserOp::Generate {
-                        dir: self.dir.to_path_buf(),
-                    },
-                    err,
-                ))
-            })
+            .map_err(generate_error)
     }
 
-    async fn install(&self) -> TsdlResult<()> {
-        // Find and install parser binary for each extension
+    /// The grammar's artifacts, by target: where they were built and where
+    /// they get installed.
+    async fn artifacts(&self) -> TsdlResult<Vec<(String, PathBuf, PathBuf)>> {
+        let mut artifacts = Vec::new();
+
         if self.spec.target.native() {
-            self.install_binary(DLL_EXTENSION).await?;
+            artifacts.push((
+                "native".to_string(),
+                self.native_binary().await?,
+                self.artifact_path("native", DLL_EXTENSION),
+            ));
         }
 
         if self.spec.target.wasm() {
-            self.install_binary(WASM_EXTENSION).await?;
+            artifacts.push((
+                "wasm".to_string(),
+                self.find_parser_binary(WASM_EXTENSION).await?,
+                self.artifact_path("wasm", WASM_EXTENSION),
+            ));
         }
 
-        Ok(())
+        if self.spec.target.static_lib() {
+            artifacts.push((
+                "static".to_string(),
+                self.static_binary(),
+                self.artifact_path("static", "a"),
+            ));
+        }
+
+        if self.spec.target.object() {
+            artifacts.push((
+                "object".to_string(),
+                self.object_binary(),
+                self.artifact_path("object", "o"),
+            ));
+        }
+
+        if self.spec.target.cross() {
+            for toolchain in &self.spec.cross {
+                artifacts.push((
+                    toolchain.triple.clone(),
+                    self.cross_binary(toolchain),
+                    self.artifact_path(&toolchain.triple, &toolchain.ext),
+                ));
+            }
+        }
+
+        Ok(artifacts)
     }
 
-    async fn install_binary(&self, ext: &str) -> TsdlResult<()> {
-        let src = self.find_parser_binary(ext).await?;
-        let dst = self.output.out_dir.join(self.parser_name_and_ext(ext));
+    async fn install(&self) -> TsdlResult<()> {
+        for (target, src, dst) in self.artifacts().await? {
+            if target != "native" {
+                self.install_file(&src, &dst).await?;
+                continue;
+            }
 
-        // Check if different file exists
-        if dst.exists() {
-            let src_metadata = fs::metadata(&src)
-                .await
-                .map_err(|e| TsdlError::context(format!("Reading {}", src.display()), e))?;
-            let dst_metadata = fs::metadata(&dst)
+            match self.chain(&dst) {
+                Some(chain) => self.install_chain(&src, &dst, &chain).await?,
+                None => self.install_file(&src, &dst).await?,
+            }
+            // The debuglink names the unversioned `.debug` file.
+            if self.spec.strip == Strip::Split {
+                self.install_file(&cc::debug_file(&src), &cc::debug_file(&dst))
+                    .await?;
+            }
+        }
+
+        self.install_queries().await?;
+        self.install_dev_files().await
+    }
+
+    /// What the manifest in `out-dir` says about this grammar once it's
+    /// installed.
+    pub async fn manifest(&self) -> TsdlResult<manifest::Grammar> {
+        let out_dir = self.output.out_dir.as_path();
+        let relative = |path: &Path| path.strip_prefix(out_dir).unwrap_or(path).to_path_buf();
+
+        let mut artifacts = BTreeMap::new();
+        for (target, src, dst) in self.artifacts().await? {
+            // A versioned library's bytes are in its real file.
+            let dst = match self.chain(&dst).filter(|_| target == "native") {
+                Some(chain) => dst.with_file_name(chain.real),
+                None => dst,
+            };
+            // What was just installed is still staged, unless it was already.
+            let staged = self.staged(&dst);
+            let installed = if fs::try_exists(&staged).await.unwrap_or(false) {
+                staged
+            } else {
+                dst.clone()
+            };
+            let artifact = manifest::Artifact {
+                path: relative(&dst),
+                sha256: cache::hash_file::<Sha256>(&src).await?,
+                mode: Some(
+                    install::resolved_mode(self.context.install_mode, &src, &installed).await,
+                ),
+                source: Some(src),
+            };
+            artifacts.insert(target, artifact);
+        }
+
+        let parser_c = fs::read_to_string(self.dir.join("src").join("parser.c")).await;
+        let abi = parser_c
+            .ok()
+            .and_then(|parser_c| manifest::abi(&parser_c))
+            .or(self.spec.abi.map(u32::from));
+
+        let root = self.output.build_dir.as_path();
+        let described = TreeSitterJson::load(root)
+            .await
+            .ok()
+            .flatten()
+            .and_then(|json| json.grammars.into_iter().find(|g| *g.name == *self.name));
+
+        Ok(manifest::Grammar {
+            language: self.language.to_string(),
+            grammar: self.name.to_string(),
+            repo: self.spec.repo.to_string(),
+            git_ref: self.spec.git_ref.to_string(),
+            commit: git::get_head_sha1(root)
                 .await
-                .map_err(|e| TsdlError::context(format!("Reading {}", dst.display()), e))?;
-
-            let src_size = src_metadata.size();
-            let dst_size = dst_metadata.size();
-            let src_inode = src_metadata.ino();
-            let dst_inode = dst_metadata.ino();
-
-            // Check if hardlink is broken (inodes don't match when they should)
-            let hardlink_broken = src_inode != dst_inode;
-
-            if src_size != dst_size || hardlink_broken {
-                if src_size != dst_size && !self.context.force {
-                    return Err(TsdlError::message(format!(
-                        "Binary differs at {}. Use --force to overwrite",
-                        dst.display()
-                    )));
-                }
+                .ok()
+                .map(|sha1| sha1.trim().to_string()),
+            tree_sitter: self.spec.tree_sitter.version.clone(),
+            abi,
+            scope: described.as_ref().and_then(|g| g.scope.clone()),
+            file_types: described.map(|g| g.file_types).unwrap_or_default(),
+            license: self.license.spdx.clone(),
+            artifacts,
+            queries: queries_dir(&self.spec, out_dir, &self.language, &self.name)
+                .map(|dir| relative(&dir)),
+            files: self
+                .installed_files()
+                .into_iter()
+                .chain(self.license.installed_files(out_dir, &self.language))
+                .map(|f| relative(&f))
+                .collect(),
+        })
+    }
 
-                fs::remove_file(&dst)
-                    .await
-                    .map_err(|e| TsdlError::context(format!("Removing {}", dst.display()), e))?;
+    /// Write the grammar's pkg-config file, `.cmake` package config and
+    /// header, with `dev-files`, pointing at where its libraries are installed.
+    async fn install_dev_files(&self) -> TsdlResult<()> {
+        if !self.has_dev_files() {
+            return Ok(());
+        }
 
-                // Report reinstallation when fixing broken hardlink
-                if hardlink_broken {
-                    if let Some(hnd) = self.context.progress.as_ref() {
-                        hnd.msg("Reinstalled");
-                    }
-                }
+        let out_dir = self.out_dir();
+        let shared =
+            (self.spec.target.native()).then(|| self.artifact_path("native", DLL_EXTENSION));
+        let static_lib = (self.spec.target.static_lib()).then(|| self.artifact_path("static", "a"));
+        let soname = shared
+            .as_deref()
+            .and_then(|dst| self.chain(dst))
+            .map(|chain| chain.soname);
+        let include_dir = out_dir.join("include");
+        let version = if let Some(version) = &self.version {
+            version.to_string()
+        } else {
+            let commit = git::get_head_sha1(&self.output.build_dir).await.ok();
+            dev_files::untagged_version(commit.as_deref().map(str::trim))
+        };
+        let artifacts = dev_files::Artifacts {
+            grammar: &self.name,
+            version: &version,
+            include_dir: &include_dir,
+            shared: shared.as_deref(),
+            soname: soname.as_deref(),
+            static_lib: static_lib.as_deref(),
+        };
 
-                // Create the hardlink after removing the old one
-                self.create_hardlink(&src, &dst).await?;
-            } else {
-                // Inodes match and sizes match - hardlink is already correct, skip
+        let [pc, cmake, header] = dev_files::paths(&out_dir, &self.name);
+        for (dst, contents) in [
+            (pc, dev_files::pkg_config(&artifacts)),
+            (cmake, dev_files::cmake_config(&artifacts)),
+            (header, dev_files::header(&self.name)),
+        ] {
+            install::stage_contents(&contents, &dst, &self.staged(&dst)).await?;
+        }
+
+        Ok(())
+    }
+
+    fn has_dev_files(&self) -> bool {
+        self.spec.dev_files && (self.spec.target.native() || self.spec.target.static_lib())
+    }
+
+    /// Install the grammar's queries, which were hashed along with its
+    /// sources, with `queries`.
+    async fn install_queries(&self) -> TsdlResult<()> {
+        let Some(out_dir) =
+            queries_dir(&self.spec, &self.output.out_dir, &self.language, &self.name)
+        else {
+            return Ok(());
+        };
+
+        let root = self.output.build_dir.as_path();
+        let dir = self.dir.strip_prefix(root).unwrap_or(Path::new(""));
+        let own = dir.join("queries");
+        let files = self.files.keys().map(Path::new).collect::<Vec<_>>();
+
+        for file in walk::query_files(&files, dir) {
+            let rel = file
+                .strip_prefix(&own)
+                .or_else(|_| file.strip_prefix("queries"))
+                .unwrap_or(&file);
+            self.install_file(&root.join(&file), &out_dir.join(rel))
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    fn artifact_path(&self, target: &str, ext: &str) -> PathBuf {
+        artifact_path(
+            &self.spec,
+            &self.output.out_dir,
+            &self.language,
+            &self.name,
+            target,
+            ext,
+        )
+    }
+
+    /// Where the grammar's artifacts, with their versioned names and debug
+    /// files, queries and dev files get installed.
+    #[must_use]
+    pub fn installed_files(&self) -> Vec<PathBuf> {
+        let mut files =
+            installed_files(&self.spec, &self.output.out_dir, &self.language, &self.name);
+        if self.spec.target.native() {
+            let native = self.artifact_path("native", DLL_EXTENSION);
+            if let Some(chain) = self.chain(&native) {
+                files.push(native.with_file_name(chain.real));
+                files.push(native.with_file_name(chain.soname));
             }
-        } else {
-            // Destination doesn't exist, create the hardlink
-            self.create_hardlink(&src, &dst).await?;
+            if self.spec.strip == Strip::Split {
+                files.push(cc::debug_file(&native));
+            }
+        }
+        files.extend(queries_dir(
+            &self.spec,
+            &self.output.out_dir,
+            &self.language,
+            &self.name,
+        ));
+        if self.has_dev_files() {
+            files.extend(dev_files::paths(&self.out_dir(), &self.name));
         }
+        files
+    }
 
+    /// Install `src` as `chain.real`, next to `dst`, with the SONAME and
+    /// unversioned symlinks leading to it.
+    async fn install_chain(&self, src: &Path, dst: &Path, chain: &Chain) -> TsdlResult<()> {
+        let out_dir = dst.parent().unwrap_or(dst);
+        self.install_file(src, &out_dir.join(&chain.real)).await?;
+        for (link, target) in [(&chain.soname, &chain.real), (&chain.link, &chain.soname)] {
+            let dst = out_dir.join(link);
+            install::stage_symlink(Path::new(target), &dst, &self.staged(&dst)).await?;
+        }
         Ok(())
     }
+
+    /// Stage `src` for `dst`, which the build's transaction moves into
+    /// place once it commits.
+    async fn install_file(&self, src: &Path, dst: &Path) -> TsdlResult<()> {
+        let installed = install::stage(
+            self.context.install_mode,
+            src,
+            dst,
+            &self.staged(dst),
+            self.context.force,
+        )
+        .await?;
+
+        // Report reinstallation when fixing a broken install
+        if installed == Installed::Replaced {
+            if let Some(hnd) = self.context.progress.as_ref() {
+                hnd.msg("Reinstalled");
+            }
+        }
+
+        Ok(())
+    }
+
     fn missing_parser_error(&self, ext: &str) -> TsdlError {
         error::TsdlError::Step(error::Step::new(
             self.language.clone(),
@@ -313,29 +852,162 @@ impl GrammarBuild {
     }
 
     /// Check if this grammar needs rebuilding based on cache
-    fn needs_rebuild(&self, _cache_key: &str) -> bool {
+    fn needs_rebuild(&self, cache_key: &str) -> bool {
         match &self.entry {
             None => true, // No cache entry - rebuild needed
             Some(entry) => {
                 // Check if hash or definition changed
                 let hash_eq = entry.hash == self.hash;
                 let def_eq = entry.spec == self.spec;
+                if !hash_eq {
+                    for change in self.files.changes(&entry.files) {
+                        debug!("{cache_key}: {change}");
+                    }
+                }
                 !(hash_eq && def_eq)
             }
         }
     }
 
+    /// Apply the `generate` policy against what's committed in `src/`: a
+    /// `parser.c` an earlier generate left behind doesn't count.
+    async fn should_generate(&self) -> TsdlResult<bool> {
+        let parser_c = self.dir.join("src").join("parser.c");
+        let committed = git::is_tracked(&self.dir, Path::new("src/parser.c")).await;
+
+        match self.spec.generate {
+            Generate::Always => Ok(true),
+            Generate::IfMissing => Ok(!committed),
+            Generate::Never if committed => Ok(false),
+            Generate::Never => Err(TsdlError::Step(error::Step::new(
+                self.language.clone(),
+                error::ParserOp::Generate {
+                    dir: self.dir.to_path_buf(),
+                },
+                TsdlError::message(format!(
+                    "{} is not committed and generate = \"never\"",
+                    parser_c.display()
+                )),
+            ))),
+        }
+    }
+
     fn parser_name_and_ext(&self, ext: &str) -> String {
         format!("{}{}.{}", self.spec.prefix, self.name, ext)
     }
 }
 
+/// Where a grammar's artifacts get installed, for every target it's built for.
+#[must_use]
+pub fn installed_files(
+    spec: &BuildSpec,
+    out_dir: &Path,
+    language: &str,
+    grammar: &str,
+) -> Vec<PathBuf> {
+    let path =
+        |target: &str, ext: &str| artifact_path(spec, out_dir, language, grammar, target, ext);
+    let mut files = Vec::new();
+
+    if spec.target.native() {
+        files.push(path("native", DLL_EXTENSION));
+    }
+    if spec.target.wasm() {
+        files.push(path("wasm", WASM_EXTENSION));
+    }
+    if spec.target.static_lib() {
+        files.push(path("static", "a"));
+    }
+    if spec.target.object() {
+        files.push(path("object", "o"));
+    }
+    if spec.target.cross() {
+        for toolchain in &spec.cross {
+            files.push(path(&toolchain.triple, &toolchain.ext));
+        }
+    }
+
+    files
+}
+
+/// Where one artifact gets installed: where `output-template` says, or
+/// `<prefix><grammar>.<ext>`, in `<triple>/` for cross builds and as
+/// `lib<prefix><grammar>.a` for static libraries.
+#[must_use]
+pub fn artifact_path(
+    spec: &BuildSpec,
+    out_dir: &Path,
+    language: &str,
+    grammar: &str,
+    target: &str,
+    ext: &str,
+) -> PathBuf {
+    // The profile's flags don't apply to wasm, built by `tree-sitter build`.
+    let out_dir = match spec.profile.subdir().filter(|_| target != "wasm") {
+        Some(subdir) => out_dir.join(subdir),
+        None => out_dir.to_path_buf(),
+    };
+
+    if let Some(template) = &spec.output_template {
+        let names = layout::Names {
+            target,
+            language,
+            prefix: &spec.prefix,
+            grammar,
+            ext,
+        };
+        return out_dir.join(layout::render(template, &names));
+    }
+
+    let file = format!("{}{grammar}.{ext}", spec.prefix);
+    match target {
+        "native" | "wasm" | "object" => out_dir.join(file),
+        "static" => out_dir.join(static_name(file)),
+        triple => out_dir.join(triple).join(file),
+    }
+}
+
+/// Whether the host's shared library gets a versioned name and SONAME.
+fn is_versioned(spec: &BuildSpec) -> bool {
+    spec.versioned && spec.target.native() && DLL_EXTENSION == "so"
+}
+
+/// Where a grammar's queries get installed, with `queries`.
+#[must_use]
+pub fn queries_dir(
+    spec: &BuildSpec,
+    out_dir: &Path,
+    language: &str,
+    grammar: &str,
+) -> Option<PathBuf> {
+    let template = spec.queries.as_deref()?;
+    let names = layout::Names {
+        target: "queries",
+        language,
+        prefix: &spec.prefix,
+        grammar,
+        ext: "scm",
+    };
+    Some(out_dir.join(layout::render(template, &names)))
+}
+
+/// `lib<file>`, without doubling a `lib` prefix.
+fn static_name(file: String) -> String {
+    if file.starts_with("lib") {
+        file
+    } else {
+        format!("lib{file}")
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct LanguageBuild {
     pub context: BuildContext,
     pub spec: Arc<BuildSpec>,
     pub name: Arc<str>,
     pub output: OutputConfig,
+    /// Only build these grammars; all of them when `None`.
+    pub grammars: Option<BTreeSet<String>>,
 }
 
 impl LanguageBuild {
@@ -351,32 +1023,112 @@ impl LanguageBuild {
             spec,
             name,
             output,
+            grammars: None,
         }
     }
 
-    pub async fn discover_grammars(&self) -> TsdlResult<Vec<(String, PathBuf, String)>> {
-        let file_results = collect_grammar_paths(self.output.build_dir.clone()).await?;
-        let mut grammars = Vec::new();
+    /// Restrict the build to `grammars`, if any.
+    #[must_use]
+    pub fn only(mut self, grammars: Option<BTreeSet<String>>) -> Self {
+        self.grammars = grammars;
+        self
+    }
 
-        for (grammar_path, hash) in file_results {
-            let grammar_dir = grammar_path.parent().ok_or_else(|| {
-                TsdlError::Message(format!(
-                    "Could not get parent directory for {}",
-                    grammar_path.display()
-                ))
-            })?;
-            let grammar_name = extract_grammar_name(grammar_dir)?;
-            grammars.push((grammar_name, grammar_dir.to_path_buf(), hash));
-        }
+    pub async fn discover_grammars(&self) -> TsdlResult<Vec<(String, PathBuf, Sources)>> {
+        let files = walk::list_inputs(&self.output.build_dir).await?;
+        self.discover_grammars_in(&files).await
+    }
 
+    /// Like [`LanguageBuild::discover_grammars`], with `files` listing the
+    /// checkout.
+    pub async fn discover_grammars_in(
+        &self,
+        files: &[PathBuf],
+    ) -> TsdlResult<Vec<(String, PathBuf, Sources)>> {
+        let mut grammars = discover_in(
+            self.output.build_dir.clone(),
+            files,
+            self.spec.generate,
+            self.spec.queries.is_some(),
+        )
+        .await?;
+        if let Some(only) = &self.grammars {
+            grammars.retain(|(name, _, _)| only.contains(name));
+        }
         Ok(grammars)
     }
 
+    /// The checkout's version, for `versioned` and `dev-files`: its version
+    /// tag, or its `tree-sitter.json` version. Versioned libraries need one.
+    pub async fn version(&self) -> TsdlResult<Option<Arc<str>>> {
+        if self.spec.versioned && self.spec.target.native() && DLL_EXTENSION != "so" {
+            warn!(
+                "{}: versioned only applies to ELF shared libraries",
+                self.name
+            );
+        }
+        if !is_versioned(&self.spec) && !self.spec.dev_files {
+            return Ok(None);
+        }
+
+        match soname::resolve(&self.output.build_dir, self.spec.git_ref.as_str()).await? {
+            Some(version) => Ok(Some(version.into())),
+            None if !is_versioned(&self.spec) => Ok(None),
+            None => Err(error::TsdlError::Step(error::Step::new(
+                self.name.clone(),
+                error::ParserOp::Build {
+                    dir: self.output.build_dir.to_path_buf(),
+                },
+                TsdlError::message(format!(
+                    "No version for {}: it isn't tagged and tree-sitter.json has none",
+                    self.spec.git_ref
+                )),
+            ))),
+        }
+    }
+
+    /// Stage the checkout's license files into `out-dir/licenses/<language>`.
+    /// Fails without any with `require-license`.
+    pub async fn license(&self) -> TsdlResult<Arc<License>> {
+        let license = License::find(&self.output.build_dir).await?;
+        if license.files.is_empty() && self.context.require_license {
+            return Err(error::TsdlError::Step(error::Step::new(
+                self.name.clone(),
+                error::ParserOp::Build {
+                    dir: self.output.build_dir.to_path_buf(),
+                },
+                TsdlError::message(format!("No LICENSE or COPYING file in {}", self.spec.repo)),
+            )));
+        }
+
+        let out_dir = self.output.out_dir.as_path();
+        let staging = install::staging_dir(out_dir, &self.name);
+        for (src, dst) in license
+            .files
+            .iter()
+            .zip(license.installed_files(out_dir, &self.name))
+        {
+            let rel = dst.strip_prefix(out_dir).unwrap_or(&dst);
+            // Licenses follow the checkout, whatever `force` says.
+            install::stage(
+                self.context.install_mode,
+                src,
+                &dst,
+                &staging.join(rel),
+                true,
+            )
+            .await?;
+        }
+
+        Ok(license.into())
+    }
+
     pub async fn clone(&self) -> TsdlResult<()> {
         clone_fast(
             self.spec.repo.as_str(),
             &self.spec.git_ref,
             &self.output.build_dir,
+            self.context.step_timeout,
         )
         .await
         .map_err(|err| {
@@ -391,6 +1143,52 @@ impl LanguageBuild {
     }
 }
 
+/// Find the grammars in a checkout: their names, directories and sources.
+///
+/// Grammars listed in `tree-sitter.json` win; repos without one (or with one
+/// that lists no grammars) are walked for `grammar.js` files instead, and
+/// names are derived from directory names.
+pub async fn discover(
+    root: Arc<PathBuf>,
+    generate: Generate,
+    queries: bool,
+) -> TsdlResult<Vec<(String, PathBuf, Sources)>> {
+    let files = walk::list_inputs(&root).await?;
+    discover_in(root, &files, generate, queries).await
+}
+
+/// Like [`discover`], among `files`, the checkout's listing.
+pub async fn discover_in(
+    root: Arc<PathBuf>,
+    files: &[PathBuf],
+    generate: Generate,
+    queries: bool,
+) -> TsdlResult<Vec<(String, PathBuf, Sources)>> {
+    match TreeSitterJson::load(&root).await {
+        Ok(Some(manifest)) if !manifest.grammars.is_empty() => {
+            return collect_manifest_grammars(root, &manifest, files, generate, queries).await;
+        }
+        Ok(_) => {}
+        Err(err) => warn!("Ignoring tree-sitter.json in {}: {err}", root.display()),
+    }
+
+    let file_results = collect_grammar_paths(root, files, generate, queries).await?;
+    let mut grammars = Vec::new();
+
+    for (grammar_path, sources) in file_results {
+        let grammar_dir = grammar_path.parent().ok_or_else(|| {
+            TsdlError::Message(format!(
+                "Could not get parent directory for {}",
+                grammar_path.display()
+            ))
+        })?;
+        let grammar_name = extract_grammar_name(grammar_dir)?;
+        grammars.push((grammar_name, grammar_dir.to_path_buf(), sources));
+    }
+
+    Ok(grammars)
+}
+
 fn extract_dir_name(dir: &Path) -> TsdlResult<String> {
     dir.file_name()
         .map(|n| n.to_string_lossy().to_string())
@@ -491,6 +1289,23 @@ mod tests {
         assert_eq!(name, "typescript.wasm");
     }
 
+    #[test]
+    fn test_artifact_path_profile() {
+        let spec = BuildSpec {
+            profile: Profile::Debug,
+            ..crate::cache::tests::spec()
+        };
+        let out = Path::new("/out");
+        assert_eq!(
+            artifact_path(&spec, out, "json", "json", "native", "so"),
+            Path::new("/out/debug/json.so")
+        );
+        assert_eq!(
+            artifact_path(&spec, out, "json", "json", "wasm", WASM_EXTENSION),
+            Path::new("/out/json.wasm")
+        );
+    }
+
     #[test]
     fn test_parser_name_with_prefix() {
         let name = parser_name_and_ext("typescript", "lib", "so");
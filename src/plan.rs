@@ -0,0 +1,430 @@
+//! `tsdl build --dry-run`: what a build would do, worked out from the cache
+//! and the checkouts in `build-dir`, without running git or a compiler.
+
+use std::{collections::HashSet, fmt, path::Path};
+
+use serde::Serialize;
+
+use crate::{
+    app::App,
+    args::Format,
+    build::{self, BuildSpec},
+    cache::{Db, Entry, Sources},
+    error::TsdlError,
+    layout::Claims,
+    parser::{self, LanguageBuild},
+    walk, TsdlResult,
+};
+
+#[derive(Debug, Serialize)]
+pub struct LanguagePlan {
+    pub language: String,
+    #[serde(rename = "ref")]
+    pub git_ref: String,
+    pub checkout: Checkout,
+    /// Empty when the grammars won't be known until the repo is cloned.
+    pub grammars: Vec<GrammarPlan>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Checkout {
+    Clone,
+    Fetch,
+    Reuse,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GrammarPlan {
+    pub grammar: String,
+    #[serde(flatten)]
+    pub action: Action,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "action", rename_all = "kebab-case")]
+pub enum Action {
+    CacheHit,
+    /// Cached, but missing from `out-dir`.
+    InstallOnly,
+    Rebuild {
+        reasons: Vec<Reason>,
+    },
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "reason", rename_all = "kebab-case")]
+pub enum Reason {
+    Forced,
+    NotCached,
+    HashChanged {
+        files: Vec<String>,
+    },
+    SpecChanged {
+        fields: Vec<String>,
+    },
+    /// The checkout gets fetched, so its sources can't be compared yet.
+    SourcesUnknown,
+}
+
+pub fn run(app: &App) -> TsdlResult<()> {
+    let db = Db::load(&app.command.build_dir)?;
+    let failed = build::retried_failures(app)?;
+    let languages = build::collect_languages(app, failed.as_ref())?;
+    let force = app.command.force || app.command.fresh;
+
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+
+    let claims = Claims::default();
+    let mut plans = rt.block_on(async {
+        let mut plans = Vec::new();
+        for language in &languages {
+            let plan = plan_language(&db, force, app.command.fresh, language).await?;
+            for grammar in &plan.grammars {
+                claims.claim(
+                    &format!("{}/{}", plan.language, grammar.grammar),
+                    &parser::installed_files(
+                        &language.spec,
+                        &language.output.out_dir,
+                        &language.name,
+                        &grammar.grammar,
+                    ),
+                )?;
+            }
+            plans.push(plan);
+        }
+        Ok::<_, TsdlError>(plans)
+    })?;
+    plans.sort_by(|a, b| a.language.cmp(&b.language));
+
+    match app.command.format {
+        Format::Text => {
+            for plan in &plans {
+                print!("{plan}");
+            }
+        }
+        Format::Json => println!("{}", serde_json::to_string_pretty(&plans)?),
+    }
+
+    Ok(())
+}
+
+async fn plan_language(
+    db: &Db,
+    force: bool,
+    fresh: bool,
+    language: &LanguageBuild,
+) -> TsdlResult<LanguagePlan> {
+    let dir = &language.output.build_dir;
+    let checked_out = !fresh && dir.join(".git").exists();
+
+    let checkout = if !force && !db.needs_clone(&language.name, &language.spec) {
+        Checkout::Reuse
+    } else if checked_out {
+        Checkout::Fetch
+    } else {
+        Checkout::Clone
+    };
+
+    let mut grammars = Vec::new();
+    if checked_out {
+        // No git: the checkout is walked, and what an earlier generate left
+        // in it counts as committed when the last build hashed it. A checkout
+        // that gets fetched still names the grammars, but its sources are
+        // the old ones, so they aren't compared.
+        let prefix = format!("{}/", language.name);
+        let hashed = db
+            .parsers
+            .range(prefix.clone()..)
+            .take_while(|(key, _)| key.starts_with(&prefix))
+            .flat_map(|(_, entry)| entry.files.keys())
+            .map(String::as_str)
+            .collect::<HashSet<_>>();
+        let files = walk::walk_inputs(dir, |file| {
+            file.to_str().is_some_and(|file| hashed.contains(file))
+        });
+        let fetched = checkout == Checkout::Fetch;
+        for (name, _, sources) in language.discover_grammars_in(&files).await? {
+            let key = format!("{}/{name}", language.name);
+            let action = action(
+                db.get(&key),
+                if fetched {
+                    Scan::Unfetched
+                } else {
+                    Scan::Checkout(&sources)
+                },
+                &language.spec,
+                force,
+                &parser::installed_files(
+                    &language.spec,
+                    &language.output.out_dir,
+                    &language.name,
+                    &name,
+                ),
+            );
+            grammars.push(GrammarPlan {
+                grammar: name,
+                action,
+            });
+        }
+    } else {
+        // Nothing to scan yet: what was built from the last checkout will
+        // be built again.
+        let prefix = format!("{}/", language.name);
+        for (key, entry) in db.parsers.range(prefix.clone()..) {
+            let Some(name) = key.strip_prefix(&prefix) else {
+                break;
+            };
+            let action = action(
+                Some(entry),
+                Scan::Missing,
+                &language.spec,
+                force,
+                &parser::installed_files(
+                    &language.spec,
+                    &language.output.out_dir,
+                    &language.name,
+                    name,
+                ),
+            );
+            grammars.push(GrammarPlan {
+                grammar: name.to_string(),
+                action,
+            });
+        }
+    }
+
+    Ok(LanguagePlan {
+        language: language.name.to_string(),
+        git_ref: language.spec.git_ref.to_string(),
+        checkout,
+        grammars,
+    })
+}
+
+/// The sources of a grammar, as far as the dry run can know them.
+#[derive(Clone, Copy)]
+enum Scan<'a> {
+    /// From the checkout the build will use.
+    Checkout(&'a Sources),
+    /// The checkout gets fetched first, so they're only known after.
+    Unfetched,
+    /// There's no checkout yet: only the spec can be compared.
+    Missing,
+}
+
+/// What happens to a grammar, the way `GrammarBuild::build` decides it.
+fn action(
+    entry: Option<&Entry>,
+    sources: Scan<'_>,
+    spec: &BuildSpec,
+    force: bool,
+    installed: &[impl AsRef<Path>],
+) -> Action {
+    let mut reasons = Vec::new();
+
+    if force {
+        reasons.push(Reason::Forced);
+    }
+
+    match entry {
+        None => reasons.push(Reason::NotCached),
+        Some(entry) => {
+            if let Scan::Checkout(sources) = sources {
+                if sources.hash().as_str() != entry.hash.as_ref() {
+                    let files = sources
+                        .changes(&entry.files)
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect();
+                    reasons.push(Reason::HashChanged { files });
+                }
+            }
+            let fields = spec_changes(&entry.spec, spec);
+            if !fields.is_empty() {
+                reasons.push(Reason::SpecChanged { fields });
+            }
+            if let Scan::Unfetched = sources {
+                reasons.push(Reason::SourcesUnknown);
+            }
+        }
+    }
+
+    if !reasons.is_empty() {
+        Action::Rebuild { reasons }
+    } else if installed.iter().all(|file| file.as_ref().exists()) {
+        Action::CacheHit
+    } else {
+        Action::InstallOnly
+    }
+}
+
+/// The `BuildSpec` fields that differ, by their name in the cache.
+fn spec_changes(old: &BuildSpec, new: &BuildSpec) -> Vec<String> {
+    let (Ok(serde_json::Value::Object(old)), Ok(serde_json::Value::Object(new))) =
+        (serde_json::to_value(old), serde_json::to_value(new))
+    else {
+        return Vec::new();
+    };
+
+    let mut fields = Vec::new();
+    for (field, value) in &new {
+        if old.get(field) != Some(value) {
+            fields.push(field.clone());
+        }
+    }
+    for field in old.keys() {
+        if !new.contains_key(field) {
+            fields.push(field.clone());
+        }
+    }
+    fields
+}
+
+impl fmt::Display for LanguagePlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let checkout = match self.checkout {
+            Checkout::Clone => "clone",
+            Checkout::Fetch => "fetch",
+            Checkout::Reuse => "reuse checkout",
+        };
+        writeln!(f, "{} {}: {checkout}", self.language, self.git_ref)?;
+
+        if self.grammars.is_empty() {
+            writeln!(f, "  grammars unknown until cloned")?;
+        }
+        for grammar in &self.grammars {
+            writeln!(f, "  {}: {}", grammar.grammar, grammar.action)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::CacheHit => write!(f, "cache hit"),
+            Action::InstallOnly => write!(f, "install only"),
+            Action::Rebuild { reasons } => {
+                write!(f, "rebuild (")?;
+                for (i, reason) in reasons.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, "; ")?;
+                    }
+                    write!(f, "{reason}")?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reason::Forced => write!(f, "forced"),
+            Reason::NotCached => write!(f, "not cached"),
+            Reason::HashChanged { files } if files.is_empty() => write!(f, "hash changed"),
+            Reason::HashChanged { files } => write!(f, "hash changed: {}", files.join(", ")),
+            Reason::SpecChanged { fields } => write!(f, "spec changed: {}", fields.join(", ")),
+            Reason::SourcesUnknown => write!(f, "sources unknown until fetched"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, sync::Arc};
+
+    use super::*;
+    use crate::cache::tests::spec;
+    use crate::git::GitRef;
+
+    fn entry(spec: BuildSpec, sources: &Sources) -> Entry {
+        Entry {
+            hash: sources.hash().into(),
+            files: sources.clone(),
+            spec: Arc::new(spec),
+        }
+    }
+
+    const NOWHERE: &[PathBuf] = &[];
+
+    #[test]
+    fn test_action_cache_hit() {
+        let sources = Sources::default();
+        let entry = entry(spec(), &sources);
+        assert_eq!(
+            action(
+                Some(&entry),
+                Scan::Checkout(&sources),
+                &spec(),
+                false,
+                NOWHERE
+            ),
+            Action::CacheHit
+        );
+        assert_eq!(
+            action(
+                Some(&entry),
+                Scan::Checkout(&sources),
+                &spec(),
+                false,
+                &[PathBuf::from("/nonexistent/libtree-sitter-json.so")]
+            ),
+            Action::InstallOnly
+        );
+    }
+
+    #[test]
+    fn test_action_rebuild_reasons() {
+        let mut sources = Sources::default();
+        let entry = entry(spec(), &sources);
+        sources.insert("grammar.js".to_string(), "abc".to_string());
+        let new_spec = BuildSpec {
+            abi: Some(14),
+            git_ref: GitRef::from("v1.0.0"),
+            ..spec()
+        };
+
+        assert_eq!(
+            action(
+                Some(&entry),
+                Scan::Checkout(&sources),
+                &new_spec,
+                true,
+                NOWHERE
+            ),
+            Action::Rebuild {
+                reasons: vec![
+                    Reason::Forced,
+                    Reason::HashChanged {
+                        files: vec!["added grammar.js".to_string()]
+                    },
+                    Reason::SpecChanged {
+                        fields: vec!["abi".to_string(), "git_ref".to_string()]
+                    },
+                ]
+            }
+        );
+        assert_eq!(
+            action(None, Scan::Checkout(&sources), &spec(), false, NOWHERE),
+            Action::Rebuild {
+                reasons: vec![Reason::NotCached]
+            }
+        ); // A fetch moves the checkout on: its old sources aren't compared.
+        assert_eq!(
+            action(Some(&entry), Scan::Unfetched, &new_spec, false, NOWHERE),
+            Action::Rebuild {
+                reasons: vec![
+                    Reason::SpecChanged {
+                        fields: vec!["abi".to_string(), "git_ref".to_string()]
+                    },
+                    Reason::SourcesUnknown,
+                ]
+            }
+        );
+    }
+}
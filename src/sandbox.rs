@@ -0,0 +1,256 @@
+//! Running build scripts and hooks in a sandbox: everything is read-only but
+//! the grammar's directory, the dirs a script is given to write to and a
+//! private `/tmp`, and there's no network.
+
+use std::{env, fmt::Write, path::Path};
+
+use tokio::process::Command;
+
+use crate::{
+    args::{Sandbox, SandboxMode},
+    error::TsdlError,
+    sh::{self, Script},
+    TsdlResult,
+};
+
+/// How a script ends up being isolated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Isolation {
+    None,
+    Bwrap,
+    Unshare,
+}
+
+/// Build the command running `script` in `dir` as `config` says; it may also
+/// write to `writable`.
+pub fn script(
+    config: &Sandbox,
+    script: &str,
+    dir: &Path,
+    writable: &[&Path],
+) -> TsdlResult<Command> {
+    let script = limited(config, script);
+    let isolation = isolation(config.mode)?;
+
+    if isolation == Isolation::None && !config.allow_unsandboxed {
+        return Err(TsdlError::message(format!(
+            "Refusing to run an unsandboxed script in {}; enable the sandbox or allow-unsandboxed",
+            dir.display()
+        )));
+    }
+
+    let mut cmd = match isolation {
+        Isolation::None => Command::from_str(&script),
+        Isolation::Bwrap => bwrap(&script, dir, writable),
+        Isolation::Unshare => unshare(&script, dir, writable),
+    };
+    cmd.current_dir(dir);
+    Ok(cmd)
+}
+
+fn isolation(mode: SandboxMode) -> TsdlResult<Isolation> {
+    let has = |program| sh::which(program).is_some();
+
+    let isolation = match mode {
+        SandboxMode::Auto if has("bwrap") => Isolation::Bwrap,
+        SandboxMode::Auto if has("unshare") => Isolation::Unshare,
+        SandboxMode::Off | SandboxMode::Auto => Isolation::None,
+        SandboxMode::Bwrap | SandboxMode::Unshare => {
+            let (program, isolation) = if mode == SandboxMode::Bwrap {
+                ("bwrap", Isolation::Bwrap)
+            } else {
+                ("unshare", Isolation::Unshare)
+            };
+            if !has(program) {
+                return Err(TsdlError::message(format!(
+                    "sandbox = \"{program}\" but it was not found in PATH"
+                )));
+            }
+            isolation
+        }
+    };
+
+    Ok(isolation)
+}
+
+fn shell() -> String {
+    env::var("SHELL").unwrap_or_else(|_| String::from("sh"))
+}
+
+/// Prefix `script` with the configured `ulimit`s.
+fn limited(config: &Sandbox, script: &str) -> String {
+    let mut res = String::new();
+    if let Some(seconds) = config.cpu_seconds {
+        _ = write!(res, "ulimit -t {seconds} && ");
+    }
+    if let Some(mb) = config.memory_mb {
+        _ = write!(res, "ulimit -v {} && ", mb * 1024);
+    }
+    res.push_str(script);
+    res
+}
+
+fn bwrap(script: &str, dir: &Path, writable: &[&Path]) -> Command {
+    let mut cmd = Command::new("bwrap");
+    cmd.args(["--ro-bind", "/", "/"])
+        .args(["--dev", "/dev"])
+        .args(["--proc", "/proc"])
+        .args(["--tmpfs", "/tmp"]);
+    for dir in std::iter::once(dir).chain(writable.iter().copied()) {
+        cmd.arg("--bind").arg(dir).arg(dir);
+    }
+    cmd.arg("--chdir")
+        .arg(dir)
+        .args(["--unshare-all", "--die-with-parent", "--new-session", "--"])
+        .arg(shell())
+        .args(["-c", script]);
+    cmd
+}
+
+/// Where the sandbox's root is put together before pivoting into it.
+const NEW_ROOT: &str = "/tmp/.tsdl-root";
+
+/// Without bubblewrap: a user namespace pivoted into a copy of `/` where
+/// every mount is read-only, with a fresh `/tmp` and `/proc`, and the grammar
+/// dir and `writable` bound over it.
+fn unshare(script: &str, dir: &Path, writable: &[&Path]) -> Command {
+    let mut cmd = Command::new("unshare");
+    cmd.args([
+        "--user",
+        "--map-root-user",
+        "--mount",
+        "--net",
+        "--ipc",
+        "--pid",
+        "--fork",
+        "--",
+    ])
+    .arg("sh")
+    .args([
+        "-c",
+        &format!("{} && exec \"$0\" -c \"$1\"", setup(dir, writable)),
+    ])
+    .arg(shell())
+    .arg(script);
+    cmd
+}
+
+/// The mounts of [`unshare`]'s sandbox.
+///
+/// The writable dirs are opened first, since the private `/tmp` hides them
+/// when they're under it. Every mount of the copy gets remounted on its own,
+/// keeping its flags: a bind remount isn't recursive, and the flags the
+/// parent namespace locked, like `nosuid`, can't be dropped.
+fn setup(dir: &Path, writable: &[&Path]) -> String {
+    let dirs = std::iter::once(dir)
+        .chain(writable.iter().copied())
+        .map(|dir| quote(&dir.to_string_lossy()))
+        .collect::<Vec<_>>();
+    let root = NEW_ROOT;
+    let mut setup = String::from("set -e && exec");
+    for (fd, dir) in (3..).zip(&dirs) {
+        _ = write!(setup, " {fd}<{dir}");
+    }
+    _ = write!(
+        setup,
+        " && mount --make-rprivate / \\
+         && mount -t tmpfs tmpfs /tmp \\
+         && mkdir {root} \\
+         && mount --rbind / {root} \\
+         && awk '$5 ~ \"^{root}(/|$)\" {{ print $5, $6 }}' /proc/self/mountinfo \\
+          | while read -r at opts; do \\
+              opts=$(printf '%s' \"$opts\" | sed 's/^rw\\b/ro/; s/,rw\\b/,ro/'); \\
+              mount -o \"remount,bind,$opts\" \"$(printf '%b' \"$at\")\"; \\
+            done \\
+         && mount -t tmpfs tmpfs {root}/tmp \\
+         && mount -t proc proc {root}/proc"
+    );
+    for (fd, dir) in (3..).zip(&dirs) {
+        _ = write!(
+            setup,
+            " && mkdir -p {root}{dir} && mount --bind /proc/self/fd/{fd} {root}{dir}"
+        );
+    }
+    for fd in (3..).take(dirs.len()) {
+        _ = write!(setup, " && exec {fd}<&-");
+    }
+    _ = write!(
+        setup,
+        " && cd {root} && pivot_root . . && umount -l . && cd {} && set +e",
+        dirs[0]
+    );
+    setup
+}
+
+/// Single-quote `s` for `sh`.
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limited() {
+        let config = Sandbox {
+            cpu_seconds: Some(60),
+            memory_mb: Some(2),
+            ..Sandbox::default()
+        };
+        assert_eq!(
+            limited(&config, "make"),
+            "ulimit -t 60 && ulimit -v 2048 && make"
+        );
+        assert_eq!(limited(&Sandbox::default(), "make"), "make");
+    }
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("/tmp/a b"), "'/tmp/a b'");
+        assert_eq!(quote("it's"), r"'it'\''s'");
+    }
+
+    #[test]
+    fn test_refuses_unsandboxed() {
+        let config = Sandbox {
+            allow_unsandboxed: false,
+            ..Sandbox::default()
+        };
+        assert!(script(&config, "make", Path::new("/tmp"), &[]).is_err());
+        assert!(script(&Sandbox::default(), "make", Path::new("/tmp"), &[]).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_unshare_is_read_only() {
+        let userns = std::process::Command::new("unshare")
+            .args(["--user", "--map-root-user", "--mount", "true"])
+            .status();
+        if !userns.is_ok_and(|status| status.success()) {
+            return;
+        }
+        let tmp = tempfile::tempdir().unwrap();
+        let (dir, out, other) = (
+            tmp.path().join("grammar"),
+            tmp.path().join("out"),
+            tmp.path().join("other"),
+        );
+        for dir in [&dir, &out, &other] {
+            std::fs::create_dir(dir).unwrap();
+        }
+        let write = |path: &Path| format!("touch {}", quote(&path.to_string_lossy()));
+
+        let script = format!(
+            "{} && {} && touch /tmp/scratch && ! {} 2>/dev/null && ! touch /dev/shm/tsdl 2>/dev/null",
+            write(&dir.join("a")),
+            write(&out.join("b")),
+            write(&other.join("c")),
+        );
+        let status = unshare(&script, &dir, &[&out]).status().await.unwrap();
+        assert!(status.success());
+        assert!(dir.join("a").exists());
+        assert!(out.join("b").exists());
+        assert!(!other.join("c").exists());
+        assert!(!Path::new("/tmp/scratch").exists());
+    }
+}
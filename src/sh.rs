@@ -1,14 +1,34 @@
-use std::{env, fmt::Write, os::unix::process::ExitStatusExt, process::Output};
+use std::{
+    collections::BTreeSet,
+    env,
+    fmt::Write,
+    os::unix::{fs::PermissionsExt, process::ExitStatusExt},
+    path::PathBuf,
+    process::{Output, Stdio},
+    sync::Mutex,
+    time::Duration,
+};
 
 use tokio::process::Command;
 use tracing::{error, trace};
 
-use crate::{error, TsdlResult};
+use crate::{cancel, error, TsdlResult};
+
+/// The process groups of the commands running right now.
+static GROUPS: Mutex<BTreeSet<i32>> = Mutex::new(BTreeSet::new());
 
 pub trait Exec {
     fn display(&self) -> TsdlResult<String>;
     fn display_full(&self) -> TsdlResult<String>;
-    fn exec(&mut self) -> impl std::future::Future<Output = TsdlResult<Output>>;
+    fn exec(&mut self) -> impl std::future::Future<Output = TsdlResult<Output>> {
+        self.exec_with(None)
+    }
+    /// Like [`Exec::exec`], killing the command's process group once it runs
+    /// longer than `timeout`.
+    fn exec_with(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> impl std::future::Future<Output = TsdlResult<Output>>;
 }
 
 pub trait Script {
@@ -45,15 +65,43 @@ impl Exec for Command {
     }
 
     #[tracing::instrument(skip(self))]
-    async fn exec(&mut self) -> TsdlResult<Output> {
+    async fn exec_with(&mut self, timeout: Option<Duration>) -> TsdlResult<Output> {
         let cmd_full = self.display_full()?;
         trace!("{}", cmd_full);
 
         let cmd = self.display()?;
-        let output = self
-            .output()
-            .await
+        let child = self
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .process_group(0)
+            .kill_on_drop(true)
+            .spawn()
             .map_err(|e| error::TsdlError::context("Failed to execute command", e))?;
+        let group = Group::new(child.id());
+
+        let expired = async {
+            match timeout {
+                Some(timeout) => tokio::time::sleep(timeout).await,
+                None => std::future::pending().await,
+            }
+        };
+
+        let output = tokio::select! {
+            output = child.wait_with_output() => output
+                .map_err(|e| error::TsdlError::context("Failed to execute command", e))?,
+            // Dropping the group kills it.
+            () = expired => {
+                let secs = timeout.map_or(0, |timeout| timeout.as_secs());
+                let msg = format!("{cmd} timed out after {secs}s.");
+                error!("{msg}");
+                return Err(error::TsdlError::message(msg));
+            }
+            () = cancel::cancelled() => {
+                return Err(error::TsdlError::message(format!("{cmd} cancelled.")));
+            }
+        };
+        group.done();
 
         if output.status.success() {
             return Ok(output);
@@ -82,6 +130,60 @@ impl Exec for Command {
     }
 }
 
+/// The process group `exec` put a command in. It's killed, grandchildren
+/// included, unless the command is done by the time it's dropped: when it
+/// timed out, or when whoever awaited it stopped polling.
+struct Group {
+    pgid: Option<i32>,
+    done: bool,
+}
+
+impl Group {
+    fn new(pgid: Option<u32>) -> Self {
+        let pgid = pgid.and_then(|pgid| i32::try_from(pgid).ok());
+        if let Some(pgid) = pgid {
+            groups().insert(pgid);
+        }
+        Self { pgid, done: false }
+    }
+
+    fn done(mut self) {
+        self.done = true;
+    }
+}
+
+impl Drop for Group {
+    fn drop(&mut self) {
+        if let Some(pgid) = self.pgid {
+            groups().remove(&pgid);
+            if !self.done {
+                kill_group(pgid);
+            }
+        }
+    }
+}
+
+/// Kill the process groups of every running command, for when the build is
+/// cancelled: the futures running them may never be polled again.
+pub fn kill_all() {
+    for pgid in groups().iter() {
+        kill_group(*pgid);
+    }
+}
+
+fn groups() -> std::sync::MutexGuard<'static, BTreeSet<i32>> {
+    GROUPS
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+fn kill_group(pgid: i32) {
+    // SAFETY: killpg has no memory-safety preconditions.
+    unsafe {
+        libc::killpg(pgid, libc::SIGKILL);
+    }
+}
+
 impl Script for Command {
     fn from_str(script: &str) -> Command {
         let shell = env::var("SHELL").unwrap_or_else(|_| String::from("sh"));
@@ -90,3 +192,61 @@ impl Script for Command {
         cmd
     }
 }
+
+/// Look `program` up in `PATH`, the way a shell would. Paths are taken as-is.
+#[must_use]
+pub fn which(program: &str) -> Option<PathBuf> {
+    let is_executable = |candidate: &PathBuf| {
+        candidate
+            .metadata()
+            .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+    };
+
+    if program.contains('/') {
+        return Some(PathBuf::from(program)).filter(is_executable);
+    }
+
+    let path = env::var_os("PATH")?;
+    env::split_paths(&path)
+        .map(|dir| dir.join(program))
+        .find(is_executable)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_timeout_kills_the_group() {
+        let start = Instant::now();
+        let res = Command::from_str("sleep 30 & sleep 30")
+            .exec_with(Some(Duration::from_secs(1)))
+            .await;
+        assert!(res.is_err_and(|e| e.to_string().contains("timed out")));
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[tokio::test]
+    async fn test_dropped_command_kills_the_group() {
+        let tmp = tempfile::tempdir().unwrap();
+        let pid_file = tmp.path().join("pid");
+        let script = format!("sleep 30 & echo $! > {}; wait", pid_file.display());
+        let mut cmd = Command::from_str(&script);
+        // The future is dropped before it's done, like a cancelled stream's.
+        let res = tokio::time::timeout(Duration::from_millis(500), cmd.exec()).await;
+        assert!(res.is_err());
+
+        let pid = std::fs::read_to_string(&pid_file).unwrap();
+        let stat = PathBuf::from(format!("/proc/{}/stat", pid.trim()));
+        for _ in 0..50 {
+            let state = std::fs::read_to_string(&stat).unwrap_or_default();
+            if state.is_empty() || state.contains(") Z ") {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("sleep {} outlived its command", pid.trim());
+    }
+}
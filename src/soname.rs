@@ -0,0 +1,178 @@
+//! Versioned shared libraries: `libtree-sitter-json.so.0.21.0`, linked with
+//! a `DT_SONAME` of `libtree-sitter-json.so.0`, which is a symlink to it, as
+//! is `libtree-sitter-json.so` to the SONAME.
+
+use std::path::Path;
+
+use crate::{git, tree_sitter_json::TreeSitterJson, TsdlResult};
+
+/// The names of a versioned library, from the real file to the link that
+/// build systems use.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Chain {
+    /// `libtree-sitter-json.so.0.21.0`
+    pub real: String,
+    /// `libtree-sitter-json.so.0`
+    pub soname: String,
+    /// `libtree-sitter-json.so`
+    pub link: String,
+}
+
+impl Chain {
+    /// The names of `file` at `version`.
+    #[must_use]
+    pub fn new(file: &str, version: &str) -> Self {
+        let major = version.split('.').next().unwrap_or(version);
+        Self {
+            real: format!("{file}.{version}"),
+            soname: format!("{file}.{major}"),
+            link: file.to_string(),
+        }
+    }
+}
+
+/// The version in a tag like `v0.21.0`, if it is one.
+#[must_use]
+pub fn from_tag(tag: &str) -> Option<String> {
+    let version = tag.strip_prefix('v').unwrap_or(tag);
+    let is_version = version
+        .split('.')
+        .all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()));
+    is_version.then(|| version.to_string())
+}
+
+/// The version of the checkout of `git_ref` in `repo`: `git_ref` itself
+/// when it's a version tag, the highest version tag on its `HEAD`, here or
+/// on `origin`, or else the version in its `tree-sitter.json`. A tag on an
+/// older commit doesn't count.
+pub async fn resolve(repo: &Path, git_ref: &str) -> TsdlResult<Option<String>> {
+    if let Some(version) = from_tag(git_ref) {
+        return Ok(Some(version));
+    }
+    let mut tags = git::tags_at(repo, "HEAD").await.unwrap_or_default();
+    if tags.is_empty() {
+        let head = git::get_head_sha1(repo).await.unwrap_or_default();
+        tags = git::remote_tags_at(repo, head.trim())
+            .await
+            .unwrap_or_default();
+    }
+    if let Some(version) = tags
+        .iter()
+        .filter_map(|tag| from_tag(tag))
+        .max_by_key(|version| parts(version))
+    {
+        return Ok(Some(version));
+    }
+
+    Ok(TreeSitterJson::load(repo)
+        .await?
+        .and_then(|manifest| manifest.metadata.version)
+        .and_then(|version| from_tag(&version)))
+}
+
+/// The numbers of a version, to compare them.
+fn parts(version: &str) -> Vec<u64> {
+    version
+        .split('.')
+        .filter_map(|part| part.parse().ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_tag() {
+        assert_eq!(from_tag("v0.21.0"), Some("0.21.0".to_string()));
+        assert_eq!(from_tag("0.23"), Some("0.23".to_string()));
+        assert_eq!(from_tag("v1.0.0-rc1"), None);
+        assert_eq!(from_tag("3f2a9c1"), None);
+        assert_eq!(from_tag("v"), None);
+    }
+
+    #[tokio::test]
+    async fn test_resolve() {
+        let tmp = tempfile::tempdir().unwrap();
+        let repo = tmp.path();
+        let git = |args: &[&str]| {
+            let status = std::process::Command::new("git")
+                .current_dir(repo)
+                .args(["-c", "user.name=t", "-c", "user.email=t@t"])
+                .args(args)
+                .status()
+                .unwrap();
+            assert!(status.success());
+        };
+        git(&["init", "-q"]);
+        git(&["commit", "-q", "--allow-empty", "-m", "one"]);
+        git(&["tag", "v0.20.0"]);
+        git(&["tag", "v0.21.0"]);
+        assert_eq!(
+            resolve(repo, "master").await.unwrap().as_deref(),
+            Some("0.21.0")
+        );
+
+        // An older commit's tag isn't this one's version.
+        git(&["commit", "-q", "--allow-empty", "-m", "two"]);
+        assert_eq!(resolve(repo, "master").await.unwrap(), None);
+        assert_eq!(
+            resolve(repo, "v0.22.0").await.unwrap().as_deref(),
+            Some("0.22.0")
+        );
+
+        tokio::fs::write(
+            repo.join("tree-sitter.json"),
+            r#"{ "grammars": [], "metadata": { "version": "0.23.0" } }"#,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            resolve(repo, "master").await.unwrap().as_deref(),
+            Some("0.23.0")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resolve_branch() {
+        let tmp = tempfile::tempdir().unwrap();
+        let remote = tmp.path().join("remote");
+        std::fs::create_dir(&remote).unwrap();
+        let git = |args: &[&str]| {
+            let status = std::process::Command::new("git")
+                .current_dir(&remote)
+                .args(["-c", "user.name=t", "-c", "user.email=t@t"])
+                .args(args)
+                .status()
+                .unwrap();
+            assert!(status.success());
+        };
+        git(&["init", "-q", "-b", "master"]);
+        git(&["commit", "-q", "--allow-empty", "-m", "one"]);
+        git(&["tag", "-a", "v0.21.0", "-m", "v0.21.0"]);
+        git(&["tag", "v0.21.1"]);
+
+        // The shallow fetch of a branch has none of its tags.
+        let repo = tmp.path().join("repo");
+        let url = format!("file://{}", remote.display());
+        crate::git::clone_fast(&url, "master", &repo, None)
+            .await
+            .unwrap();
+        assert_eq!(
+            resolve(&repo, "master").await.unwrap().as_deref(),
+            Some("0.21.1")
+        );
+    }
+
+    #[test]
+    fn test_chain() {
+        assert_eq!(
+            Chain::new("libtree-sitter-json.so", "0.21.0"),
+            Chain {
+                real: "libtree-sitter-json.so.0.21.0".to_string(),
+                soname: "libtree-sitter-json.so.0".to_string(),
+                link: "libtree-sitter-json.so".to_string(),
+            }
+        );
+    }
+}
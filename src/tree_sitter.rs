@@ -6,7 +6,7 @@ use std::str::FromStr;
 
 use async_compression::tokio::bufread::GzipDecoder;
 use tokio::{fs, io, process::Command};
-use tracing::trace;
+use tracing::{trace, warn};
 use url::Url;
 
 use crate::actors::{DisplayAddr, ProgressAddr};
@@ -34,11 +34,11 @@ async fn cli(
     repo: &str,
     tag: &Tag,
 ) -> TsdlResult<PathBuf> {
+    let tree_sitter = PathBuf::new().join(build_dir).join("tree-sitter");
     let tag = match tag {
         Tag::Exact { label, .. } => Cow::Borrowed(label),
         Tag::Ref(git_ref) => {
-            handle.msg(format!("Figuring out the exact tag for {tag}",));
-            let tree_sitter = PathBuf::new().join(build_dir).join("tree-sitter");
+            handle.msg(format!("Figuring out the exact tag for {tag}"));
             git::clone(repo, &tree_sitter).await?;
             Cow::Owned(git::tag_for_ref(&tree_sitter, git_ref).await?)
         }
@@ -50,39 +50,92 @@ async fn cli(
         .canon()?;
 
     if !res.exists() {
-        handle.msg(format!("Downloading {tag}",));
+        handle.msg(format!("Downloading {tag}"));
         let gz_basename = format!("{cli}.gz");
         let url = format!("{repo}/releases/download/{tag}/{gz_basename}");
         let gz = PathBuf::new().join(build_dir).join(gz_basename);
 
-        download_and_extract(&gz, &url, &res).await?;
+        let downloaded = download_and_extract(&gz, &url, &res).await;
+        if !matches!(downloaded, Ok(true)) {
+            remove_if_exists(&gz).await?;
+            remove_if_exists(&res).await?;
+        }
+        if !downloaded? {
+            warn!("No prebuilt tree-sitter-cli at {url}");
+            handle.step(format!("Compiling {tag} from source (no prebuilt {cli})"));
+            compile(&tree_sitter, repo, &tag, &res).await?;
+        }
     }
 
     Ok(res)
 }
 
-async fn download(gz: &Path, url: &str) -> TsdlResult<()> {
+/// Build the cli from the tree-sitter repo when no release asset matches the
+/// platform, e.g. musl hosts, whose platform falls back to the target triple.
+///
+/// It builds `tag` from a worktree of the clone made to resolve tags, so the
+/// clone stays on its branch and later `git pull`s keep working even when a
+/// build is interrupted. Builds share the clone's `target` directory.
+async fn compile(tree_sitter: &Path, repo: &str, tag: &str, res: &Path) -> TsdlResult<()> {
+    git::clone(repo, tree_sitter).await?;
+    let worktree = tree_sitter.with_file_name(format!("tree-sitter-{tag}-src"));
+    // What an interrupted build left behind.
+    git::remove_worktree(tree_sitter, &worktree).await?;
+    git::add_worktree(tree_sitter, &worktree, tag).await?;
+
+    let built = Command::new("cargo")
+        .current_dir(&worktree)
+        .env("CARGO_TARGET_DIR", tree_sitter.join("target"))
+        .args(["build", "--release", "-p", "tree-sitter-cli"])
+        .exec()
+        .await
+        .map_err(|e| TsdlError::context(format!("Compiling tree-sitter-cli {tag} with cargo"), e));
+
+    git::remove_worktree(tree_sitter, &worktree).await?;
+    built?;
+
+    let bin = tree_sitter
+        .join("target")
+        .join("release")
+        .join("tree-sitter");
+    fs::copy(&bin, res).await.map_err(|e| {
+        TsdlError::context(format!("copying {} to {}", bin.display(), res.display()), e)
+    })?;
+    chmod_x(res).await
+}
+
+/// Download `url` to `gz`; `false` when the release has no such asset.
+async fn download(gz: &Path, url: &str) -> TsdlResult<bool> {
+    let response = reqwest::get(url)
+        .await
+        .map_err(|e| TsdlError::context("fetch", e))?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(false);
+    }
     fs::write(
         gz,
-        reqwest::get(url)
-            .await
+        response
+            .error_for_status()
             .map_err(|e| TsdlError::context("fetch", e))?
             .bytes()
             .await
             .map_err(|e| TsdlError::context("fetching bytes", e))?,
     )
     .await
-    .map_err(|e| TsdlError::context(format!("downloading {url} to {}", gz.display()), e))
+    .map_err(|e| TsdlError::context(format!("downloading {url} to {}", gz.display()), e))?;
+    Ok(true)
 }
 
-async fn download_and_extract(gz: &Path, url: &str, res: &Path) -> TsdlResult<()> {
-    download(gz, url).await?;
+async fn download_and_extract(gz: &Path, url: &str, res: &Path) -> TsdlResult<bool> {
+    if !download(gz, url).await? {
+        return Ok(false);
+    }
     gunzip(gz, res).await?;
     chmod_x(res).await?;
     fs::remove_file(gz)
         .await
         .map_err(|e| TsdlError::context(format!("removing {}", gz.display()), e))?;
-    Ok(())
+    Ok(true)
 }
 
 fn find_tag(refs: &HashMap<String, String>, version: &str) -> Tag {
@@ -117,6 +170,15 @@ async fn gunzip(gz: &Path, to: &Path) -> TsdlResult<()> {
         .map_err(|e| TsdlError::context(format!("decompressing {}", gz.display()), e))
 }
 
+async fn remove_if_exists(path: &Path) -> TsdlResult<()> {
+    if path.exists() {
+        fs::remove_file(path)
+            .await
+            .map_err(|e| TsdlError::context(format!("removing {}", path.display()), e))?;
+    }
+    Ok(())
+}
+
 fn parse_refs(stdout: &str) -> HashMap<String, String> {
     let mut refs = HashMap::new();
 
@@ -153,7 +215,7 @@ pub async fn prepare(
     progress.step(format!("Figuring out tag from ref {git_ref}"));
     let tag = tag(repo.as_str(), git_ref).await?;
 
-    progress.step(format!("Fetching {tag}",));
+    progress.step(format!("Fetching {tag}"));
     let cli = cli(
         build_dir,
         &progress,
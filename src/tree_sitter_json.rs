@@ -0,0 +1,177 @@
+//! The `tree-sitter.json` manifest that newer grammar repos ship at their
+//! root. Only the parts tsdl cares about are modeled; everything else is
+//! ignored.
+
+use std::path::{Component, Path, PathBuf};
+
+use serde::Deserialize;
+use tokio::fs;
+
+use crate::{error::TsdlError, TsdlResult};
+
+pub const TREE_SITTER_JSON: &str = "tree-sitter.json";
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+pub struct TreeSitterJson {
+    #[serde(default)]
+    pub grammars: Vec<Grammar>,
+    #[serde(default)]
+    pub metadata: Metadata,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub struct Grammar {
+    pub name: String,
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+    #[serde(default)]
+    pub scope: Option<String>,
+    #[serde(default)]
+    pub file_types: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+pub struct Metadata {
+    #[serde(default)]
+    pub version: Option<String>,
+    #[serde(default)]
+    pub license: Option<String>,
+}
+
+impl Grammar {
+    /// The grammar's name, once it's a C identifier like tree-sitter wants:
+    /// it names the parser's files, its queries and its cache entry.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if `name` isn't `[A-Za-z_][A-Za-z0-9_]*`.
+    pub fn checked_name(&self) -> TsdlResult<&str> {
+        let mut chars = self.name.chars();
+        let valid = chars
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if valid {
+            Ok(&self.name)
+        } else {
+            Err(TsdlError::message(format!(
+                "Grammar {:?} in {TREE_SITTER_JSON} isn't a C identifier",
+                self.name
+            )))
+        }
+    }
+
+    /// Directory of the grammar, relative to the repo root.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if `path` leads out of the repo: it's absolute or
+    /// goes through `..`.
+    pub fn dir(&self) -> TsdlResult<PathBuf> {
+        let Some(path) = self.path.as_deref() else {
+            return Ok(PathBuf::new());
+        };
+        path.components()
+            .filter(|c| !matches!(c, Component::CurDir))
+            .map(|c| match c {
+                Component::Normal(part) => Ok(part),
+                _ => Err(TsdlError::message(format!(
+                    "Grammar {} in {TREE_SITTER_JSON} has a path outside the repo: {}",
+                    self.name,
+                    path.display()
+                ))),
+            })
+            .collect()
+    }
+}
+
+impl TreeSitterJson {
+    /// Read the manifest at the root of `repo`, if there is one.
+    pub async fn load(repo: &Path) -> TsdlResult<Option<Self>> {
+        let path = repo.join(TREE_SITTER_JSON);
+        if !fs::try_exists(&path).await.unwrap_or(false) {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(&path)
+            .await
+            .map_err(|e| TsdlError::context(format!("reading {}", path.display()), e))?;
+        let manifest = serde_json::from_str(&content)
+            .map_err(|e| TsdlError::context(format!("parsing {}", path.display()), e))?;
+        Ok(Some(manifest))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let manifest: TreeSitterJson = serde_json::from_str(
+            r#"{
+                "grammars": [
+                    {
+                        "name": "php",
+                        "camelcase": "PHP",
+                        "scope": "source.php",
+                        "path": "php",
+                        "file-types": ["php"],
+                        "highlights": "queries/highlights.scm"
+                    },
+                    { "name": "php_only", "scope": "source.php", "path": "php_only" }
+                ],
+                "metadata": { "version": "0.24.2", "license": "MIT" },
+                "bindings": { "c": true }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(manifest.grammars.len(), 2);
+        assert_eq!(manifest.grammars[0].name, "php");
+        assert_eq!(manifest.grammars[0].file_types, vec!["php"]);
+        assert_eq!(
+            manifest.grammars[1].dir().unwrap(),
+            PathBuf::from("php_only")
+        );
+        assert_eq!(manifest.metadata.license.as_deref(), Some("MIT"));
+    }
+
+    #[test]
+    fn test_dir_defaults_to_root() {
+        let grammar: Grammar = serde_json::from_str(r#"{ "name": "json" }"#).unwrap();
+        assert_eq!(grammar.dir().unwrap(), PathBuf::new());
+
+        let grammar: Grammar = serde_json::from_str(r#"{ "name": "json", "path": "." }"#).unwrap();
+        assert_eq!(grammar.dir().unwrap(), PathBuf::new());
+    }
+
+    #[test]
+    fn test_dir_stays_in_the_repo() {
+        for path in ["..", "php/../../other", "/usr/include"] {
+            let grammar = Grammar {
+                name: "php".to_string(),
+                path: Some(PathBuf::from(path)),
+                scope: None,
+                file_types: Vec::new(),
+            };
+            let err = grammar.dir().unwrap_err().to_string();
+            assert!(err.contains("Grammar php"), "{path}: {err}");
+        }
+    }
+
+    #[test]
+    fn test_checked_name() {
+        let grammar = |name: &str| Grammar {
+            name: name.to_string(),
+            path: None,
+            scope: None,
+            file_types: Vec::new(),
+        };
+        assert_eq!(grammar("c_sharp").checked_name().unwrap(), "c_sharp");
+        assert_eq!(grammar("_tsx2").checked_name().unwrap(), "_tsx2");
+        for name in ["", "../x", "a/b", "2fa", "php-only", "json.so"] {
+            assert!(grammar(name).checked_name().is_err(), "{name}");
+        }
+    }
+}
@@ -0,0 +1,474 @@
+//! `tsdl verify`: check that `out-dir` holds exactly what the manifest says
+//! was installed, and put back what it can from `build-dir` with `--repair`.
+
+use std::{
+    fmt,
+    path::{Component, Path, PathBuf},
+};
+
+use sha2::Sha256;
+use tokio::fs;
+
+use crate::{
+    app::App,
+    args::{InstallCommit, InstallMode},
+    build,
+    cache::{self, Db},
+    consts::{TSDL_LOCK_FILE, TSDL_MANIFEST_FILE},
+    error::TsdlError,
+    install::{self, Transaction},
+    manifest::Manifest,
+    TsdlResult,
+};
+
+/// What's wrong with a file in `out-dir`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Problem {
+    Missing,
+    /// Its SHA-256 isn't the one it was installed with.
+    Mismatch(String),
+    /// It has the right contents, but isn't a link to its build anymore.
+    Unlinked(InstallMode),
+    /// No grammar in the manifest installed it.
+    Extra,
+    /// It's what was installed, but `build-dir` has since been built from
+    /// another ref, which never got installed.
+    Outdated(String),
+}
+
+/// A file of `out-dir` that doesn't match the manifest.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Finding {
+    /// Relative to `out-dir`.
+    pub path: PathBuf,
+    /// The `language/grammar` that installed it.
+    pub owner: Option<String>,
+    pub problem: Problem,
+    /// Where it can be reinstalled from: its build, if it still has the
+    /// contents the manifest records.
+    pub source: Option<PathBuf>,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(owner) = &self.owner {
+            write!(f, "{owner}: ")?;
+        }
+        write!(f, "{}: ", self.path.display())?;
+        match &self.problem {
+            Problem::Missing => write!(f, "missing"),
+            Problem::Mismatch(sha256) => write!(f, "checksum mismatch ({})", short(sha256)),
+            Problem::Unlinked(mode) => write!(
+                f,
+                "not a {} to its build anymore",
+                format!("{mode:?}").to_lowercase()
+            ),
+            Problem::Extra => write!(f, "not in the manifest"),
+            Problem::Outdated(git_ref) => write!(f, "build-dir was built from {git_ref} since"),
+        }
+    }
+}
+
+pub fn run(app: &App, repair: bool) -> TsdlResult<()> {
+    let out_dir = &app.command.out_dir;
+    if !Manifest::file(out_dir).exists() {
+        return Err(TsdlError::message(format!(
+            "No {TSDL_MANIFEST_FILE} in {}; build first",
+            out_dir.display()
+        )));
+    }
+
+    let _guard = if repair {
+        Some(build::lock(app)?)
+    } else {
+        None
+    };
+    let manifest = Manifest::load(out_dir)?;
+    let cache = Db::load(&app.command.build_dir)?;
+    let mode = app.command.install_mode;
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+
+    let findings = rt.block_on(check(out_dir, &manifest, &cache, mode))?;
+    let repaired = if repair {
+        rt.block_on(reinstall(out_dir, &manifest, mode, &findings))?
+    } else {
+        Vec::new()
+    };
+
+    let mut failed = 0;
+    for finding in &findings {
+        if repaired.contains(&finding.path) {
+            println!("{finding}: repaired");
+        } else if repair && finding.source.is_none() {
+            failed += 1;
+            println!("{finding}: can't repair, rebuild it with --force");
+        } else {
+            failed += 1;
+            println!("{finding}");
+        }
+    }
+
+    if failed > 0 {
+        return Err(TsdlError::message(format!(
+            "{failed} files in {} don't match {TSDL_MANIFEST_FILE}",
+            out_dir.display()
+        )));
+    }
+
+    println!(
+        "{} matches {TSDL_MANIFEST_FILE} ({} grammars)",
+        out_dir.display(),
+        manifest.grammars.len()
+    );
+    Ok(())
+}
+
+/// Rehash what the manifest lists in `out_dir`, and look for what it
+/// doesn't. Artifacts are checked against how they were installed, or
+/// `mode` for manifests that don't say, and against what `cache` says
+/// `build-dir` holds now.
+pub async fn check(
+    out_dir: &Path,
+    manifest: &Manifest,
+    cache: &Db,
+    mode: InstallMode,
+) -> TsdlResult<Vec<Finding>> {
+    let mut findings = Vec::new();
+
+    for (owner, grammar) in &manifest.grammars {
+        // The build the grammar was installed from, if `build-dir` still has it.
+        let rebuilt = cache
+            .get(owner)
+            .map(|entry| &entry.spec.git_ref)
+            .filter(|git_ref| git_ref.as_str() != grammar.git_ref);
+        for artifact in grammar.artifacts.values() {
+            let dst = out_dir.join(&artifact.path);
+            let mode = artifact.mode.unwrap_or(mode);
+            let source = match &artifact.source {
+                Some(src) if rebuilt.is_none() && is_intact(src, &artifact.sha256).await => {
+                    Some(src.clone())
+                }
+                _ => None,
+            };
+
+            let problem = if fs::try_exists(&dst).await.unwrap_or(false) {
+                let sha256 = cache::hash_file::<Sha256>(&dst).await?;
+                if sha256 != artifact.sha256 {
+                    Problem::Mismatch(sha256)
+                } else if let Some(git_ref) = rebuilt {
+                    Problem::Outdated(git_ref.to_string())
+                } else if let Some(src) = source.as_deref().filter(|_| is_link(mode)) {
+                    if install::is_installed(mode, src, &dst).await? {
+                        continue;
+                    }
+                    Problem::Unlinked(mode)
+                } else {
+                    continue;
+                }
+            } else {
+                Problem::Missing
+            };
+
+            findings.push(Finding {
+                path: artifact.path.clone(),
+                owner: Some(owner.clone()),
+                problem,
+                source,
+            });
+        }
+
+        for path in &grammar.files {
+            let listed = grammar.artifacts.values().any(|a| a.path == *path);
+            if !listed && !fs::try_exists(out_dir.join(path)).await.unwrap_or(false) {
+                findings.push(Finding {
+                    path: path.clone(),
+                    owner: Some(owner.clone()),
+                    problem: Problem::Missing,
+                    source: None,
+                });
+            }
+        }
+    }
+
+    for path in install::files(out_dir)? {
+        if !is_tsdl_own(&path) && !is_listed(manifest, &path) {
+            findings.push(Finding {
+                path,
+                owner: None,
+                problem: Problem::Extra,
+                source: None,
+            });
+        }
+    }
+
+    Ok(findings)
+}
+
+/// Reinstall what `findings` can be from `build-dir`, in one transaction
+/// that `tsdl rollback` can undo. Returns the repaired paths.
+async fn reinstall(
+    out_dir: &Path,
+    manifest: &Manifest,
+    mode: InstallMode,
+    findings: &[Finding],
+) -> TsdlResult<Vec<PathBuf>> {
+    let transaction = Transaction::new(out_dir, InstallCommit::Run)?;
+    let mut repaired = Vec::new();
+
+    for finding in findings {
+        let (Some(owner), Some(src)) = (&finding.owner, &finding.source) else {
+            continue;
+        };
+        let Some(grammar) = manifest.grammars.get(owner) else {
+            continue;
+        };
+        let mode = grammar
+            .artifacts
+            .values()
+            .find(|artifact| artifact.path == finding.path)
+            .and_then(|artifact| artifact.mode)
+            .unwrap_or(mode);
+        let dst = out_dir.join(&finding.path);
+        let staged = install::staging_dir(out_dir, &grammar.language).join(&finding.path);
+        install::stage(mode, src, &dst, &staged, true).await?;
+        repaired.push(finding.path.clone());
+    }
+
+    transaction.finish(true)?;
+    Ok(repaired)
+}
+
+async fn is_intact(src: &Path, sha256: &str) -> bool {
+    cache::hash_file::<Sha256>(src)
+        .await
+        .is_ok_and(|actual| actual == sha256)
+}
+
+/// Whether `mode` leaves installed files linked to their build.
+fn is_link(mode: InstallMode) -> bool {
+    matches!(mode, InstallMode::Hardlink | InstallMode::Symlink)
+}
+
+/// The manifest, the lock, and what staging and rollback keep in `out-dir`.
+fn is_tsdl_own(path: &Path) -> bool {
+    path == Path::new(TSDL_MANIFEST_FILE)
+        || path == Path::new(TSDL_LOCK_FILE)
+        || matches!(
+            path.components().next(),
+            Some(Component::Normal(first))
+                if first == install::STAGING_DIR || first == install::PREVIOUS_DIR
+        )
+}
+
+fn is_listed(manifest: &Manifest, path: &Path) -> bool {
+    manifest
+        .grammars
+        .values()
+        .flat_map(|grammar| &grammar.files)
+        .any(|file| path.starts_with(file))
+}
+
+fn short(hash: &str) -> &str {
+    &hash[..hash.len().min(7)]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::manifest;
+
+    struct Setup {
+        _tmp: tempfile::TempDir,
+        out_dir: PathBuf,
+        src: PathBuf,
+        manifest: Manifest,
+        cache: Db,
+    }
+
+    async fn setup(mode: InstallMode) -> Setup {
+        let tmp = tempfile::tempdir().unwrap();
+        let src = tmp.path().join("build").join("libtree-sitter-json.so");
+        let out_dir = tmp.path().join("out");
+        fs::create_dir_all(src.parent().unwrap()).await.unwrap();
+        fs::create_dir_all(out_dir.join("queries").join("json"))
+            .await
+            .unwrap();
+        fs::write(&src, b"parser").await.unwrap();
+        fs::write(
+            out_dir.join("queries").join("json").join("highlights.scm"),
+            "",
+        )
+        .await
+        .unwrap();
+
+        let path = PathBuf::from("libtree-sitter-json.so");
+        let staged = install::staging_dir(&out_dir, "json").join(&path);
+        let transaction = Transaction::new(&out_dir, InstallCommit::Run).unwrap();
+        install::stage(mode, &src, &out_dir.join(&path), &staged, false)
+            .await
+            .unwrap();
+        transaction.finish(true).unwrap();
+
+        let artifact = manifest::Artifact {
+            path: path.clone(),
+            sha256: cache::hash_file::<Sha256>(&src).await.unwrap(),
+            source: Some(src.clone()),
+            mode: Some(install::resolved_mode(mode, &src, &out_dir.join(&path)).await),
+        };
+        let mut manifest = Manifest::default();
+        manifest.update([manifest::Grammar {
+            language: "json".to_string(),
+            grammar: "json".to_string(),
+            repo: "https://github.com/tree-sitter/tree-sitter-json".to_string(),
+            git_ref: "master".to_string(),
+            commit: None,
+            tree_sitter: "0.26.5".to_string(),
+            abi: Some(14),
+            scope: None,
+            file_types: Vec::new(),
+            license: None,
+            artifacts: BTreeMap::from([("native".to_string(), artifact)]),
+            queries: None,
+            files: vec![path, PathBuf::from("queries/json")],
+        }]);
+        fs::write(Manifest::file(&out_dir), manifest.to_json().unwrap())
+            .await
+            .unwrap();
+
+        Setup {
+            _tmp: tmp,
+            out_dir,
+            src,
+            manifest,
+            cache: Db::default(),
+        }
+    }
+
+    impl Setup {
+        async fn check(&self, mode: InstallMode) -> Vec<Finding> {
+            check(&self.out_dir, &self.manifest, &self.cache, mode)
+                .await
+                .unwrap()
+        }
+
+        fn dst(&self) -> PathBuf {
+            self.out_dir.join("libtree-sitter-json.so")
+        }
+    }
+
+    #[tokio::test]
+    async fn test_intact() {
+        let s = setup(InstallMode::Hardlink).await;
+        assert_eq!(s.check(InstallMode::Hardlink).await, []);
+    }
+
+    #[tokio::test]
+    async fn test_missing_and_extra() {
+        let s = setup(InstallMode::Copy).await;
+        fs::remove_file(s.dst()).await.unwrap();
+        fs::write(s.out_dir.join("libtree-sitter-yaml.so"), b"yaml")
+            .await
+            .unwrap();
+
+        let problems = s
+            .check(InstallMode::Copy)
+            .await
+            .into_iter()
+            .map(|f| (f.path, f.problem))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            problems,
+            [
+                (PathBuf::from("libtree-sitter-json.so"), Problem::Missing),
+                (PathBuf::from("libtree-sitter-yaml.so"), Problem::Extra),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_broken_hardlink_repaired() {
+        let s = setup(InstallMode::Hardlink).await;
+        // Same contents, but another inode.
+        fs::remove_file(s.dst()).await.unwrap();
+        fs::copy(&s.src, s.dst()).await.unwrap();
+
+        let findings = s.check(InstallMode::Hardlink).await;
+        assert_eq!(findings.len(), 1);
+        assert_eq!(
+            findings[0].problem,
+            Problem::Unlinked(InstallMode::Hardlink)
+        );
+
+        let repaired = reinstall(&s.out_dir, &s.manifest, InstallMode::Hardlink, &findings)
+            .await
+            .unwrap();
+        assert_eq!(repaired, [PathBuf::from("libtree-sitter-json.so")]);
+        assert_eq!(s.check(InstallMode::Hardlink).await, []);
+    }
+
+    #[tokio::test]
+    async fn test_broken_auto_hardlink() {
+        let s = setup(InstallMode::Auto).await;
+        assert_eq!(
+            s.manifest.grammars["json/json"].artifacts["native"].mode,
+            Some(InstallMode::Hardlink)
+        );
+        fs::remove_file(s.dst()).await.unwrap();
+        fs::copy(&s.src, s.dst()).await.unwrap();
+
+        // `auto` is checked as the hardlink it made.
+        let findings = s.check(InstallMode::Auto).await;
+        assert_eq!(findings.len(), 1);
+        assert_eq!(
+            findings[0].problem,
+            Problem::Unlinked(InstallMode::Hardlink)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_outdated() {
+        use crate::{
+            build::BuildSpec,
+            cache::{tests::spec, Entry, Sources},
+        };
+
+        let mut s = setup(InstallMode::Hardlink).await;
+        let spec = BuildSpec {
+            git_ref: "v0.24.8".into(),
+            ..spec()
+        };
+        s.cache.parsers.insert(
+            "json/json".to_string(),
+            Entry {
+                hash: "abc".into(),
+                files: Sources::default(),
+                spec: spec.into(),
+            },
+        );
+
+        let findings = s.check(InstallMode::Hardlink).await;
+        assert_eq!(findings.len(), 1);
+        assert_eq!(
+            findings[0].problem,
+            Problem::Outdated("v0.24.8".to_string())
+        );
+        assert_eq!(findings[0].source, None);
+    }
+
+    #[tokio::test]
+    async fn test_mismatch_without_build() {
+        let s = setup(InstallMode::Copy).await;
+        fs::write(s.dst(), b"tampered").await.unwrap();
+        fs::write(&s.src, b"rebuilt").await.unwrap();
+
+        let findings = s.check(InstallMode::Copy).await;
+        assert_eq!(findings.len(), 1);
+        assert!(matches!(findings[0].problem, Problem::Mismatch(_)));
+        // The build changed since, so there's nothing to repair from.
+        assert_eq!(findings[0].source, None);
+    }
+}
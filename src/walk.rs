@@ -1,158 +1,534 @@
-use async_stream::try_stream;
-use futures::{Stream, StreamExt};
-use ignore::{
-    gitignore::{Gitignore, GitignoreBuilder},
-    overrides::Override,
-    types::Types,
-};
-use std::io;
-use std::path::{Path, PathBuf};
+use sha1::Sha1;
+use std::collections::{BTreeSet, HashSet};
+use std::path::{Component, Path, PathBuf};
 use std::sync::Arc;
 use tokio::fs;
 
-use crate::cache;
+use crate::args::Generate;
+use crate::cache::{self, Sources};
+use crate::git;
+use crate::tree_sitter_json::{TreeSitterJson, TREE_SITTER_JSON};
 
-/// Holds the immutable rules for the traversal.
-struct FilterContext {
-    types: Types,
-    overrides: Override,
+/// What `tree-sitter generate` writes into a grammar's `src/`.
+const GENERATED: &[&str] = &["grammar.json", "node-types.json", "parser.c", "tree_sitter"];
+
+/// Extensions of hand-written scanner sources and headers.
+const SOURCE_EXTENSIONS: &[&str] = &["c", "cc", "cpp", "cxx", "h", "hh", "hpp", "hxx"];
+
+/// Collect grammar.js paths among `files`, the repo's listing, along with the
+/// sources behind each.
+pub async fn collect_grammar_paths(
+    root: Arc<PathBuf>,
+    files: &[PathBuf],
+    generate: Generate,
+    queries: bool,
+) -> crate::TsdlResult<Vec<(PathBuf, Sources)>> {
+    let mut results = Vec::new();
+
+    for file in git::grammar_files(files) {
+        let dir = file.parent().unwrap_or_else(|| Path::new(""));
+        let sources = collect_sources(&root, files, dir, generate, queries).await?;
+        results.push((root.join(&file), sources));
+    }
+
+    Ok(results)
 }
 
-impl FilterContext {
-    fn new(root: impl AsRef<Path>) -> Self {
-        use ignore::{overrides::OverrideBuilder, types::TypesBuilder};
+/// Collect the grammars listed in a repo's `tree-sitter.json` along with the
+/// sources behind each. Returns `(name, dir, sources)` with `dir` absolute.
+pub async fn collect_manifest_grammars(
+    root: Arc<PathBuf>,
+    manifest: &TreeSitterJson,
+    files: &[PathBuf],
+    generate: Generate,
+    queries: bool,
+) -> crate::TsdlResult<Vec<(String, PathBuf, Sources)>> {
+    let mut results = Vec::new();
 
-        let mut types_builder = TypesBuilder::new();
-        types_builder.add_def("js:*.js").unwrap();
-        let types = types_builder.select("js").build().unwrap();
+    for grammar in &manifest.grammars {
+        let name = grammar.checked_name()?;
+        let dir = grammar.dir()?;
+        let sources = collect_sources(&root, files, &dir, generate, queries).await?;
+        results.push((name.to_string(), root.join(&dir), sources));
+    }
 
-        let mut overrides_builder = OverrideBuilder::new(root);
-        overrides_builder.case_insensitive(true).unwrap();
-        overrides_builder
-            .add("!(.github|bindings|doc|docs|examples|queries|script|scripts|test|tests)/**")
-            .unwrap();
-        let overrides = overrides_builder.build().unwrap();
+    Ok(results)
+}
 
-        Self { types, overrides }
+/// The files of the repo in `root` that can be inputs: all of them but what
+/// an earlier `tree-sitter generate` wrote and isn't committed.
+pub async fn list_inputs(root: &Path) -> crate::TsdlResult<Vec<PathBuf>> {
+    let untracked = git::list_untracked(root)
+        .await?
+        .into_iter()
+        .filter(|file| is_generated(file))
+        .collect::<HashSet<_>>();
+    let mut files = git::list_files(root).await?;
+    files.retain(|file| !untracked.contains(file));
+    Ok(files)
+}
+
+/// Like [`list_inputs`], without git: the checkout in `root` is walked,
+/// minding its ignore files, and what `tree-sitter generate` writes only
+/// counts when `committed` says so.
+#[must_use]
+pub fn walk_inputs(root: &Path, committed: impl Fn(&Path) -> bool) -> Vec<PathBuf> {
+    let mut files = ignore::WalkBuilder::new(root)
+        .hidden(false)
+        .filter_entry(|entry| entry.file_name() != ".git")
+        .build()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_some_and(|t| t.is_file()))
+        .filter_map(|entry| entry.path().strip_prefix(root).ok().map(Path::to_path_buf))
+        .filter(|file| !is_generated(file) || committed(file))
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+}
+
+/// Hash everything that affects the parser built from the grammar in `dir`:
+/// `grammar.js` and the JS modules it `require`s, the hand-written sources
+/// and headers in `src/` and what they `#include` from the rest of the repo,
+/// and `tree-sitter.json`. When the `generate` policy
+/// may build from the committed `src/`, what `tree-sitter generate` writes
+/// there is hashed too, and so are the grammar's queries when they get
+/// installed.
+///
+/// `root` is the repo root, `files` its file listing, and `dir` is relative
+/// to `root`.
+pub async fn collect_sources(
+    root: &Path,
+    files: &[PathBuf],
+    dir: &Path,
+    generate: Generate,
+    queries: bool,
+) -> crate::TsdlResult<Sources> {
+    let listed = files.iter().map(PathBuf::as_path).collect::<HashSet<_>>();
+    let src = dir.join("src");
+
+    let mut paths = files
+        .iter()
+        .filter(|file| is_source(&src, file, generate.uses_committed()))
+        .cloned()
+        .collect::<BTreeSet<_>>();
+
+    if queries {
+        let listed = files.iter().map(PathBuf::as_path).collect::<Vec<_>>();
+        paths.extend(query_files(&listed, dir));
     }
 
-    fn is_ignored(&self, path: &Path, is_dir: bool, gitignore: &Gitignore) -> bool {
-        if gitignore.matched(path, is_dir).is_ignore()
-            && !self.overrides.matched(path, is_dir).is_whitelist()
-        {
-            return true;
+    for manifest in [PathBuf::from(TREE_SITTER_JSON), dir.join(TREE_SITTER_JSON)] {
+        if listed.contains(manifest.as_path()) {
+            paths.insert(manifest);
         }
-        false
     }
-}
 
-/// Recursive async generator
-fn scan_directory(
-    dir: PathBuf,
-    ctx: Arc<FilterContext>,
-    parent_ignore: Arc<Gitignore>,
-) -> impl Stream<Item = io::Result<PathBuf>> {
-    try_stream! {
-        // 1. Check for a local .gitignore in this folder
-        // If it exists, we must create a new matcher for this scope.
-        // If not, we reuse the parent's matcher (cheap pointer copy).
-        let local_ignore_path = dir.join(".gitignore");
-        let active_ignore = if fs::try_exists(&local_ignore_path).await.unwrap_or(false) {
-            let mut builder = GitignoreBuilder::new(&dir);
-            builder.add(local_ignore_path);
-            // Note: In a production 'ignore' replacement, you would chain
-            // the parent_ignore here. For simplicity, we just build local.
-            Arc::new(builder.build().unwrap())
-        } else {
-            parent_ignore
+    let mut pending = vec![dir.join("grammar.js")];
+    while let Some(module) = pending.pop() {
+        if paths.contains(&module) {
+            continue;
+        }
+        let Ok(content) = fs::read_to_string(root.join(&module)).await else {
+            continue;
         };
+        let base = module.parent().unwrap_or_else(|| Path::new(""));
+        pending.extend(
+            requires(&content)
+                .into_iter()
+                .filter_map(|spec| resolve(base, spec, &listed)),
+        );
+        paths.insert(module);
+    }
 
-        // 2. Open the directory stream
-        let mut read_dir = fs::read_dir(&dir).await?;
+    // What the C/C++ sources `#include` from elsewhere in the repo, like
+    // `typescript/src/scanner.c` does `../../common/scanner.h`.
+    let mut pending = paths
+        .iter()
+        .filter(|path| path.starts_with(&src))
+        .cloned()
+        .collect::<Vec<_>>();
+    let mut seen = HashSet::new();
+    while let Some(file) = pending.pop() {
+        if !seen.insert(file.clone()) {
+            continue;
+        }
+        let Ok(content) = fs::read_to_string(root.join(&file)).await else {
+            continue;
+        };
+        let base = file.parent().unwrap_or_else(|| Path::new(""));
+        for spec in includes(&content) {
+            let path = normalize(&base.join(spec));
+            let generated = is_generated(&path) && !generate.uses_committed();
+            if listed.contains(path.as_path()) && !generated {
+                paths.insert(path.clone());
+                pending.push(path);
+            }
+        }
+    }
 
-        // 3. Iterate over entries
-        while let Some(entry) = read_dir.next_entry().await? {
-            let path = entry.path();
-            let metadata = entry.metadata().await?;
-            let is_dir = metadata.is_dir();
-            let is_file = metadata.is_file();
+    let mut sources = Sources::default();
+    for path in paths {
+        let full_path = root.join(&path);
+        if full_path.is_file() {
+            let hash = cache::hash_file::<Sha1>(&full_path).await?;
+            sources.insert(path.to_string_lossy().to_string(), hash);
+        }
+    }
 
-            // 4. Check Filters
-            if ctx.is_ignored(&path, is_dir, &active_ignore) {
-                continue;
-            }
+    Ok(sources)
+}
 
-            if is_dir {
-                // RECURSION:
-                // We recursively call this function and yield results from the sub-stream
-                let mut sub_stream = Box::pin(scan_directory(
-                    path,
-                    ctx.clone(),
-                    active_ignore.clone()
-                ));
-
-                while let Some(result) = sub_stream.next().await {
-                    yield result?;
-                }
-            } else if is_file
-                && is_grammar_file(&path, &ctx.types) {
-                    yield path;
-                }
+/// The grammar in `dir`'s queries among `files`: the `.scm` files in
+/// `dir/queries/`, or in the repo's `queries/` when it has none of its own,
+/// like `tsx` and `typescript` sharing theirs.
+#[must_use]
+pub fn query_files(files: &[&Path], dir: &Path) -> Vec<PathBuf> {
+    let in_dir = |queries: &Path| {
+        files
+            .iter()
+            .filter(|file| {
+                file.starts_with(queries) && file.extension().is_some_and(|ext| ext == "scm")
+            })
+            .map(|file| file.to_path_buf())
+            .collect::<Vec<_>>()
+    };
+
+    let own = in_dir(&dir.join("queries"));
+    if own.is_empty() {
+        in_dir(Path::new("queries"))
+    } else {
+        own
+    }
+}
+
+/// Whether `file` is something `tree-sitter generate` writes into a `src/`.
+fn is_generated(file: &Path) -> bool {
+    let mut components = file.components().map(Component::as_os_str);
+    while let Some(component) = components.next() {
+        if component == "src" {
+            return components
+                .next()
+                .is_some_and(|next| GENERATED.iter().any(|g| next == *g));
         }
     }
+    false
 }
 
-/// Check if filename is "grammar.js" AND the path is not ignored by types
-fn is_grammar_file(path: &Path, types: &Types) -> bool {
-    path.file_name() == Some("grammar.js".as_ref()) && !types.matched(path, false).is_ignore()
+/// Whether `file` is a hand-written C/C++ source or header under `src`.
+/// What `tree-sitter generate` writes there is output, not input, unless
+/// `generated` says we build from it.
+fn is_source(src: &Path, file: &Path, generated: bool) -> bool {
+    let Ok(rel) = file.strip_prefix(src) else {
+        return false;
+    };
+
+    let is_generated = rel
+        .components()
+        .next()
+        .is_some_and(|first| GENERATED.iter().any(|g| first.as_os_str() == *g));
+
+    if is_generated {
+        return generated;
+    }
+
+    file.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| SOURCE_EXTENSIONS.contains(&ext))
 }
 
-/// Collect grammar.js paths and compute their hashes in a single stream.
-/// Returns a stream of (path, hash) tuples.
-///
-/// # Panics
-///
-///
-pub fn collect_grammar_paths_with_hash(
-    root: PathBuf,
-) -> impl Stream<Item = io::Result<(PathBuf, String)>> {
-    let ctx = Arc::new(FilterContext::new(root.clone()));
-
-    let mut builder = GitignoreBuilder::new(&root);
-    builder.add(root.join(".gitignore"));
-    let root_ignore = Arc::new(
-        builder
-            .build()
-            .unwrap_or_else(|_| panic!("gitignore builder failed")),
-    );
-
-    try_stream! {
-        let mut stream = Box::pin(scan_directory(root, ctx, root_ignore));
-        while let Some(path_result) = stream.next().await {
-            let path = path_result?;
-            let hash = cache::hash_file(&path).await.map_err(|e| {
-                io::Error::other(format!("Failed to hash {}: {}", path.display(), e))
-            })?;
-            yield (path, hash);
+fn normalize(path: &Path) -> PathBuf {
+    let mut res = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                res.pop();
+            }
+            other => res.push(other),
         }
     }
+    res
 }
 
-/// Collect grammar.js paths via git ls-files and compute their hashes.
-/// Uses git for file enumeration (truly async, avoids blocking thread pool).
-pub async fn collect_grammar_paths(
-    root: Arc<PathBuf>,
-) -> crate::TsdlResult<Vec<(PathBuf, String)>> {
-    use crate::git;
+/// Relative module specifiers passed to `require` in `source`.
+fn requires(source: &str) -> Vec<&str> {
+    let mut res = Vec::new();
+    let mut rest = source;
 
-    let files = git::list_grammar_files(&root).await?;
-    let mut results = Vec::new();
+    while let Some(idx) = rest.find("require(") {
+        rest = rest[idx + "require(".len()..].trim_start();
+        let Some(quote) = rest
+            .chars()
+            .next()
+            .filter(|c| matches!(c, '\'' | '"' | '`'))
+        else {
+            continue;
+        };
+        rest = &rest[1..];
+        let Some(end) = rest.find(quote) else {
+            break;
+        };
+        let spec = &rest[..end];
+        if spec.starts_with("./") || spec.starts_with("../") {
+            res.push(spec);
+        }
+        rest = &rest[end + 1..];
+    }
+
+    res
+}
+
+/// The paths in `source`'s `#include "..."` directives; `<...>` ones are
+/// the system's.
+fn includes(source: &str) -> Vec<&str> {
+    source
+        .lines()
+        .filter_map(|line| {
+            let directive = line.trim_start().strip_prefix('#')?.trim_start();
+            let rest = directive.strip_prefix("include")?.trim_start();
+            let rest = rest.strip_prefix('"')?;
+            rest.find('"').map(|end| &rest[..end])
+        })
+        .collect()
+}
 
-    for file in files {
-        let full_path = root.join(&file);
-        let hash = cache::hash_file(&full_path).await?;
-        results.push((full_path, hash));
+/// Resolve a `require` specifier against the module's directory the way node
+/// does for relative paths, keeping only files that are part of the repo.
+fn resolve(base: &Path, spec: &str, listed: &HashSet<&Path>) -> Option<PathBuf> {
+    let path = normalize(&base.join(spec));
+    [
+        path.clone(),
+        path.with_extension("js"),
+        path.with_extension("json"),
+        path.join("index.js"),
+    ]
+    .into_iter()
+    .find(|candidate| listed.contains(candidate.as_path()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_requires() {
+        let source = r#"
+            const common = require('./common/define-grammar');
+            const other = require("../shared/rules.js");
+            const path = require('path');
+            module.exports = require( `./tsx` );
+        "#;
+        assert_eq!(
+            requires(source),
+            vec!["./common/define-grammar", "../shared/rules.js", "./tsx"]
+        );
     }
 
-    Ok(results)
+    #[test]
+    fn test_includes() {
+        let source = r#"
+            #include "tree_sitter/parser.h"
+            #include <wctype.h>
+            # include "../../common/scanner.h"
+            // #include "commented.h"
+        "#;
+        assert_eq!(
+            includes(source),
+            vec!["tree_sitter/parser.h", "../../common/scanner.h"]
+        );
+    }
+
+    #[test]
+    fn test_resolve() {
+        let files = [
+            PathBuf::from("common/define-grammar.js"),
+            PathBuf::from("tsx/grammar.js"),
+        ];
+        let listed = files.iter().map(PathBuf::as_path).collect::<HashSet<_>>();
+
+        assert_eq!(
+            resolve(Path::new("tsx"), "../common/define-grammar", &listed),
+            Some(PathBuf::from("common/define-grammar.js"))
+        );
+        assert_eq!(resolve(Path::new("tsx"), "./missing", &listed), None);
+    }
+
+    #[test]
+    fn test_query_files() {
+        let files = [
+            PathBuf::from("queries/highlights.scm"),
+            PathBuf::from("queries/locals.scm"),
+            PathBuf::from("tsx/grammar.js"),
+            PathBuf::from("markdown/queries/injections.scm"),
+            PathBuf::from("markdown/queries/README.md"),
+        ];
+        let files = files.iter().map(PathBuf::as_path).collect::<Vec<_>>();
+
+        assert_eq!(
+            query_files(&files, Path::new("tsx")),
+            vec![
+                PathBuf::from("queries/highlights.scm"),
+                PathBuf::from("queries/locals.scm")
+            ]
+        );
+        assert_eq!(
+            query_files(&files, Path::new("markdown")),
+            vec![PathBuf::from("markdown/queries/injections.scm")]
+        );
+    }
+
+    #[test]
+    fn test_is_source() {
+        let src = Path::new("tsx/src");
+        assert!(is_source(src, Path::new("tsx/src/scanner.c"), false));
+        assert!(is_source(src, Path::new("tsx/src/tag.h"), false));
+        assert!(!is_source(src, Path::new("tsx/src/parser.c"), false));
+        assert!(!is_source(src, Path::new("tsx/src/grammar.json"), false));
+        assert!(!is_source(
+            src,
+            Path::new("tsx/src/tree_sitter/parser.h"),
+            false
+        ));
+        assert!(!is_source(
+            src,
+            Path::new("typescript/src/scanner.c"),
+            false
+        ));
+    }
+
+    #[test]
+    fn test_is_generated() {
+        assert!(is_generated(Path::new("src/parser.c")));
+        assert!(is_generated(Path::new("tsx/src/tree_sitter/parser.h")));
+        assert!(is_generated(Path::new("tsx/src/grammar.json")));
+        assert!(!is_generated(Path::new("tsx/src/scanner.c")));
+        assert!(!is_generated(Path::new("parser.c")));
+    }
+
+    #[tokio::test]
+    async fn test_collect_sources_follows_includes() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let files = [
+            "common/scanner.h",
+            "common/unused.h",
+            "typescript/grammar.js",
+            "typescript/src/scanner.c",
+            "typescript/src/parser.c",
+            "typescript/src/tree_sitter/parser.h",
+        ]
+        .map(PathBuf::from);
+        for file in &files {
+            fs::create_dir_all(root.join(file).parent().unwrap())
+                .await
+                .unwrap();
+            fs::write(root.join(file), "").await.unwrap();
+        }
+        fs::write(
+            root.join("typescript/src/scanner.c"),
+            "#include \"tree_sitter/parser.h\"\n#include \"../../common/scanner.h\"\n",
+        )
+        .await
+        .unwrap();
+
+        let sources = collect_sources(
+            root,
+            &files,
+            Path::new("typescript"),
+            Generate::Always,
+            false,
+        )
+        .await
+        .unwrap();
+        let hashed = sources.keys().map(String::as_str).collect::<Vec<_>>();
+        assert_eq!(
+            hashed,
+            [
+                "common/scanner.h",
+                "typescript/grammar.js",
+                "typescript/src/scanner.c"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_list_inputs_skips_untracked_generated() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        fs::create_dir_all(root.join("src")).await.unwrap();
+        for file in ["grammar.js", "src/scanner.c", "src/parser.c"] {
+            fs::write(root.join(file), "").await.unwrap();
+        }
+        let git = |args: &[&str]| {
+            std::process::Command::new("git")
+                .current_dir(root)
+                .args(args)
+                .output()
+                .unwrap()
+        };
+        git(&["init", "--quiet"]);
+        git(&["add", "grammar.js", "src/scanner.c"]);
+
+        let mut files = list_inputs(root).await.unwrap();
+        files.sort();
+        assert_eq!(
+            files,
+            [PathBuf::from("grammar.js"), PathBuf::from("src/scanner.c")]
+        );
+        assert!(!crate::git::is_tracked(root, Path::new("src/parser.c")).await);
+
+        git(&["add", "src/parser.c"]);
+        assert!(list_inputs(root)
+            .await
+            .unwrap()
+            .contains(&PathBuf::from("src/parser.c")));
+        assert!(crate::git::is_tracked(root, Path::new("src/parser.c")).await);
+    }
+
+    #[tokio::test]
+    async fn test_walk_inputs() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        for dir in [".git", "src", "node_modules"] {
+            fs::create_dir_all(root.join(dir)).await.unwrap();
+        }
+        for file in [
+            ".git/HEAD",
+            ".gitignore",
+            "grammar.js",
+            "node_modules/dep.js",
+            "src/scanner.c",
+            "src/parser.c",
+        ] {
+            fs::write(root.join(file), "").await.unwrap();
+        }
+        fs::write(root.join(".gitignore"), "node_modules\n")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            walk_inputs(root, |_| false),
+            [
+                PathBuf::from(".gitignore"),
+                PathBuf::from("grammar.js"),
+                PathBuf::from("src/scanner.c"),
+            ]
+        );
+        assert!(walk_inputs(root, |file| file == Path::new("src/parser.c"))
+            .contains(&PathBuf::from("src/parser.c")));
+    }
+
+    #[test]
+    fn test_is_source_with_generated() {
+        let src = Path::new("tsx/src");
+        assert!(is_source(src, Path::new("tsx/src/scanner.c"), true));
+        assert!(is_source(src, Path::new("tsx/src/parser.c"), true));
+        assert!(is_source(src, Path::new("tsx/src/node-types.json"), true));
+        assert!(is_source(
+            src,
+            Path::new("tsx/src/tree_sitter/parser.h"),
+            true
+        ));
+        assert!(!is_source(src, Path::new("tsx/src/README.md"), true));
+    }
 }
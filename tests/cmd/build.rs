@@ -1,14 +1,14 @@
 use std::{env::consts::DLL_EXTENSION, os::unix::fs::PermissionsExt};
 
-use assert_cmd::Command;
+use assert_cmd::{cargo::cargo_bin_cmd, Command};
 use assert_fs::prelude::*;
 use indoc::{formatdoc, indoc};
 use predicates::{self as p};
 use rstest::*;
 
 use tsdl::consts::{
-    TREE_SITTER_PLATFORM, TREE_SITTER_VERSION, TSDL_BUILD_DIR, TSDL_CONFIG_FILE, TSDL_OUT_DIR,
-    TSDL_PREFIX,
+    TREE_SITTER_PLATFORM, TREE_SITTER_VERSION, TSDL_BUILD_DIR, TSDL_CONFIG_FILE, TSDL_FAILED_FILE,
+    TSDL_MANIFEST_FILE, TSDL_OUT_DIR, TSDL_PREFIX,
 };
 
 #[cfg(enable_wasm_cases)]
@@ -343,6 +343,371 @@ fn build_target(#[case] target: Option<&str>, #[case] exts: &[&str]) {
     }
 }
 
+#[rstest]
+fn build_target_set() {
+    let mut sandbox = Sandbox::new();
+    sandbox
+        .tmp
+        .child(TSDL_CONFIG_FILE)
+        .write_str(indoc! {r#"
+            target = ["native", "static", "object"]
+
+            [parsers]
+            json = "0.21.0"
+        "#})
+        .unwrap();
+    sandbox.cmd.args(["build"]).assert().success();
+    for name in [
+        format!("{TSDL_PREFIX}json.{DLL_EXTENSION}"),
+        format!("{TSDL_PREFIX}json.a"),
+        format!("{TSDL_PREFIX}json.o"),
+    ] {
+        sandbox
+            .tmp
+            .child(TSDL_OUT_DIR)
+            .child(name)
+            .assert(p::path::exists())
+            .assert(p::path::is_file());
+    }
+}
+
+#[rstest]
+fn build_profile_goes_to_its_own_dir() {
+    let mut sandbox = Sandbox::new();
+    sandbox
+        .cmd
+        .args([
+            "build",
+            "json",
+            "--build-profile",
+            "debug",
+            "--strip",
+            "split",
+        ])
+        .assert()
+        .success();
+    let debug = sandbox.tmp.child(TSDL_OUT_DIR).child("debug");
+    let lib = format!("{TSDL_PREFIX}json.{DLL_EXTENSION}");
+    debug
+        .child(&lib)
+        .assert(p::path::exists())
+        .assert(p::path::is_file());
+    debug
+        .child(format!("{lib}.debug"))
+        .assert(p::path::exists())
+        .assert(p::path::is_file());
+    sandbox
+        .tmp
+        .child(TSDL_OUT_DIR)
+        .child(&lib)
+        .assert(p::path::missing());
+}
+
+#[rstest]
+fn build_hooks_get_the_environment() {
+    let mut sandbox = Sandbox::new();
+    sandbox
+        .tmp
+        .child(TSDL_CONFIG_FILE)
+        .write_str(indoc! {r#"
+            [hooks]
+            post-install = 'echo "$TSDL_HOOK $TSDL_LANGUAGE $TSDL_GRAMMAR $TSDL_TARGET" > "$TSDL_OUT_DIR/hook.txt"'
+
+            [parsers]
+            json = "0.21.0"
+        "#})
+        .unwrap();
+    sandbox.cmd.args(["build"]).assert().success();
+    sandbox
+        .tmp
+        .child(TSDL_OUT_DIR)
+        .child("hook.txt")
+        .assert("post-install json json native\n");
+
+    // It runs after cached installs too.
+    let hook = sandbox.tmp.child(TSDL_OUT_DIR).child("hook.txt");
+    std::fs::remove_file(hook.path()).unwrap();
+    let mut cmd = cargo_bin_cmd!();
+    cmd.current_dir(sandbox.tmp.path());
+    cmd.args(["build"]).assert().success();
+    hook.assert("post-install json json native\n");
+}
+
+#[rstest]
+fn build_failing_hook_should_fail() {
+    let mut sandbox = Sandbox::new();
+    sandbox
+        .tmp
+        .child(TSDL_CONFIG_FILE)
+        .write_str(indoc! {r#"
+            [parsers]
+            json = { ref = "0.21.0", hooks = { pre-build = "exit 3" } }
+        "#})
+        .unwrap();
+    sandbox
+        .cmd
+        .args(["build"])
+        .assert()
+        .failure()
+        .stderr(p::str::contains("Hook pre-build failed"));
+}
+
+#[rstest]
+#[case::static_lib(&["--target", "static"])]
+#[case::cross(&["--cross", "aarch64-linux-gnu"])]
+fn build_script_rejects_compiled_targets(#[case] args: &[&str]) {
+    let mut sandbox = Sandbox::new();
+    sandbox
+        .tmp
+        .child(TSDL_CONFIG_FILE)
+        .write_str(indoc! {r#"
+            [parsers]
+            json = { ref = "0.21.0", cmd = "make" }
+        "#})
+        .unwrap();
+    sandbox
+        .cmd
+        .arg("build")
+        .args(args)
+        .assert()
+        .failure()
+        .stderr(p::str::contains(
+            "only supports the native and wasm targets",
+        ));
+}
+
+#[rstest]
+fn build_fail_fast_summarizes() {
+    let mut sandbox = Sandbox::new();
+    sandbox
+        .cmd
+        .args(["build", "jsonxxx", "--fail-fast"])
+        .assert()
+        .failure()
+        .stdout(p::str::contains("Failed: jsonxxx"));
+}
+
+#[rstest]
+fn build_failed_without_failures() {
+    let mut sandbox = Sandbox::new();
+    sandbox
+        .cmd
+        .args(["build", "--failed"])
+        .assert()
+        .success()
+        .stdout(p::str::contains("No failures from the last run"));
+}
+
+#[rstest]
+fn build_failed_retries_last_failures() {
+    let mut sandbox = Sandbox::new();
+    sandbox.cmd.args(["build", "jsonxxx"]).assert().failure();
+    sandbox
+        .tmp
+        .child(TSDL_BUILD_DIR)
+        .child(TSDL_FAILED_FILE)
+        .assert(p::str::contains("jsonxxx"));
+
+    let mut cmd = cargo_bin_cmd!();
+    cmd.current_dir(sandbox.tmp.path());
+    cmd.args(["build", "--failed"])
+        .assert()
+        .failure()
+        .stdout(p::str::contains(
+            "Retrying failures from the last run: jsonxxx",
+        ));
+}
+
+#[rstest]
+fn build_dry_run_plans_without_cloning() {
+    let mut sandbox = Sandbox::new();
+    sandbox
+        .cmd
+        .args(["build", "json", "--dry-run", "--format", "json"])
+        .assert()
+        .success()
+        .stdout(p::str::contains(r#""checkout": "clone""#));
+    sandbox
+        .tmp
+        .child(TSDL_BUILD_DIR)
+        .child("tree-sitter-json")
+        .assert(p::path::missing());
+}
+
+#[rstest]
+fn build_queries_per_grammar() {
+    let mut sandbox = Sandbox::new();
+    sandbox
+        .cmd
+        .args(["build", "typescript", "--queries"])
+        .assert()
+        .success();
+    let queries = sandbox.tmp.child(TSDL_OUT_DIR).child("queries");
+    for grammar in ["tsx", "typescript"] {
+        queries
+            .child(grammar)
+            .child("highlights.scm")
+            .assert(p::path::is_file());
+    }
+}
+
+#[rstest]
+fn build_dev_files() {
+    let mut sandbox = Sandbox::new();
+    sandbox
+        .cmd
+        .args(["build", "json", "--dev-files", "--target", "native,static"])
+        .assert()
+        .success();
+    let out_dir = sandbox.tmp.child(TSDL_OUT_DIR);
+    out_dir
+        .child("pkgconfig")
+        .child("tree-sitter-json.pc")
+        .assert(p::str::contains(format!(
+            "{TSDL_PREFIX}json.{DLL_EXTENSION}"
+        )));
+    out_dir
+        .child("cmake")
+        .child("tree-sitter-json")
+        .child("tree-sitter-jsonConfig.cmake")
+        .assert(p::str::contains("tree-sitter::json-static"));
+    out_dir
+        .child("include")
+        .child("tree_sitter")
+        .child("tree-sitter-json.h")
+        .assert(p::str::contains(
+            "const TSLanguage *tree_sitter_json(void);",
+        ));
+}
+
+#[rstest]
+fn build_manifest() {
+    let mut sandbox = Sandbox::new();
+    sandbox.cmd.args(["build", "json"]).assert().success();
+    let manifest = sandbox.tmp.child(TSDL_OUT_DIR).child(TSDL_MANIFEST_FILE);
+    manifest.assert(p::str::contains("\"json/json\""));
+    manifest.assert(p::str::contains(format!(
+        "\"path\": \"{TSDL_PREFIX}json.{DLL_EXTENSION}\""
+    )));
+    manifest.assert(p::str::contains("\"sha256\""));
+
+    // Building another language keeps json listed.
+    let mut cmd = cargo_bin_cmd!();
+    cmd.current_dir(sandbox.tmp.path());
+    cmd.args(["build", "yaml"]).assert().success();
+    manifest.assert(p::str::contains("\"json/json\""));
+    manifest.assert(p::str::contains("\"yaml/yaml\""));
+}
+
+#[rstest]
+fn build_licenses() {
+    let mut sandbox = Sandbox::new();
+    sandbox
+        .cmd
+        .args(["build", "json", "--require-license"])
+        .assert()
+        .success();
+    let out_dir = sandbox.tmp.child(TSDL_OUT_DIR);
+    out_dir
+        .child("licenses")
+        .child("json")
+        .child("LICENSE")
+        .assert(p::path::is_file());
+    out_dir
+        .child(TSDL_MANIFEST_FILE)
+        .assert(p::str::contains("\"license\": \"MIT\""));
+}
+
+#[rstest]
+fn verify_without_manifest_fails() {
+    let mut sandbox = Sandbox::new();
+    sandbox
+        .cmd
+        .arg("verify")
+        .assert()
+        .failure()
+        .stderr(p::str::contains(format!("No {TSDL_MANIFEST_FILE} in")));
+}
+
+#[rstest]
+fn verify_detects_and_repairs() {
+    let mut sandbox = Sandbox::new();
+    sandbox.cmd.args(["build", "json"]).assert().success();
+    let dylib = sandbox
+        .tmp
+        .child(TSDL_OUT_DIR)
+        .child(format!("{TSDL_PREFIX}json.{DLL_EXTENSION}"));
+    std::fs::remove_file(dylib.path()).unwrap();
+
+    let verify = |args: &[&str]| {
+        let mut cmd = cargo_bin_cmd!();
+        cmd.current_dir(sandbox.tmp.path());
+        cmd.arg("verify").args(args).assert()
+    };
+    verify(&[])
+        .failure()
+        .stdout(p::str::contains("json/json: "))
+        .stdout(p::str::contains("missing"));
+    verify(&["--repair"])
+        .success()
+        .stdout(p::str::contains("repaired"));
+    dylib.assert(p::path::exists());
+    verify(&[]).success();
+}
+
+#[rstest]
+#[case::tar_gz("tar.gz")]
+#[case::zip("zip")]
+fn pack_then_unpack(#[case] format: &str) {
+    let mut sandbox = Sandbox::new();
+    sandbox.cmd.args(["build", "json"]).assert().success();
+    let tsdl = |args: &[&str]| {
+        let mut cmd = cargo_bin_cmd!();
+        cmd.current_dir(sandbox.tmp.path());
+        cmd.args(args).assert()
+    };
+    tsdl(&["pack", "--format", format]).success();
+    let out_dir = sandbox.tmp.child(TSDL_OUT_DIR);
+    std::fs::remove_dir_all(out_dir.path()).unwrap();
+
+    let archive = format!("{TSDL_OUT_DIR}.{format}");
+    tsdl(&["unpack", &archive])
+        .success()
+        .stdout(p::str::contains("Unpacked 1 grammars"));
+    out_dir
+        .child(format!("{TSDL_PREFIX}json.{DLL_EXTENSION}"))
+        .assert(p::path::is_file());
+    out_dir
+        .child(TSDL_MANIFEST_FILE)
+        .assert(p::str::contains("\"json/json\""));
+}
+
+#[rstest]
+fn unpack_unknown_format_fails() {
+    let mut sandbox = Sandbox::new();
+    sandbox
+        .cmd
+        .args(["unpack", "parsers.rar"])
+        .assert()
+        .failure()
+        .stderr(p::str::contains("Can't tell the format of parsers.rar"));
+}
+
+#[rstest]
+fn build_output_template_unknown_placeholder() {
+    let mut sandbox = Sandbox::new();
+    sandbox
+        .cmd
+        .args(["build", "json", "--output-template", "{name}.{ext}"])
+        .assert()
+        .failure();
+    sandbox
+        .tmp
+        .child(TSDL_BUILD_DIR)
+        .child("tree-sitter-json")
+        .assert(p::path::missing());
+}
+
 #[rstest]
 fn build_plain_progress_numbered_correctly() {
     let mut sandbox = Sandbox::new();
@@ -5,7 +5,7 @@ use indoc::{formatdoc, indoc};
 use pretty_assertions::{assert_eq, assert_ne};
 
 use tsdl::{
-    args::BuildCommand,
+    args::{BuildCommand, Target},
     config,
     consts::{
         TREE_SITTER_PLATFORM, TREE_SITTER_REPO, TREE_SITTER_VERSION, TSDL_BUILD_DIR, TSDL_FRESH,
@@ -105,3 +105,22 @@ fn current_overrides_default() -> Result<()> {
     assert_ne!(def, config::current(&generated, Some(&def)).unwrap());
     Ok(())
 }
+
+#[test]
+fn current_target_one_or_many() -> Result<()> {
+    let temp = assert_fs::TempDir::new()?;
+    let generated = temp.child("generated.toml");
+
+    generated.write_str(r#"target = "wasm""#)?;
+    assert_eq!(
+        vec![Target::Wasm],
+        config::current(&generated, None).unwrap().target
+    );
+
+    generated.write_str(r#"target = ["native", "static"]"#)?;
+    assert_eq!(
+        vec![Target::Native, Target::Static],
+        config::current(&generated, None).unwrap().target
+    );
+    Ok(())
+}